@@ -0,0 +1,206 @@
+//! Compares the bitset archetype against the sorted vector of component IDs it replaced, using
+//! the set operations queries and the dispatcher rely on.
+//!
+//! Run with `cargo bench --bench archetype`.
+
+use std::{
+    alloc::Layout,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use cecs::{
+    archetype::Archetype,
+    component::registry::{ComponentId, ComponentRegistry},
+};
+
+// Shared with the fuzz tests, which use all of it
+#[allow(dead_code)]
+#[path = "../src/rng.rs"]
+mod rng;
+
+use rng::Rng;
+
+/// Number of distinct component types in the simulated world.
+const COMPONENTS: usize = 256;
+/// Number of archetypes in the simulated world.
+const ARCHETYPES: usize = 500;
+/// Number of queries matched against every archetype.
+const QUERIES: usize = 100;
+/// Number of timed passes over every query.
+const ITERATIONS: usize = 100;
+
+/// The archetype representation before it was replaced by a bitset.
+#[derive(Default, Clone)]
+struct SortedArchetype {
+    ids: Vec<ComponentId>,
+}
+
+impl SortedArchetype {
+    fn add_component_by_id(&mut self, id: ComponentId) {
+        self.ids.push(id);
+        self.ids.sort_unstable();
+    }
+
+    fn any_of(&self, other: &SortedArchetype) -> bool {
+        let mut self_i = 0;
+        let mut other_i = 0;
+
+        while self_i != self.ids.len() && other_i != other.ids.len() {
+            let self_id = self.ids[self_i];
+            let other_id = other.ids[other_i];
+
+            if self_id == other_id {
+                return true;
+            } else if self_id < other_id {
+                self_i += 1;
+            } else {
+                other_i += 1;
+            }
+        }
+
+        false
+    }
+
+    fn subset_of(&self, other: &SortedArchetype) -> bool {
+        if self.ids.is_empty() {
+            return true;
+        }
+
+        let mut i = 0;
+        for ty in &other.ids {
+            if *ty == self.ids[i] {
+                i += 1;
+                if i == self.ids.len() {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+/// Random sets of distinct component indices with sizes in `min..=max`.
+fn random_sets(rng: &mut Rng, count: usize, min: usize, max: usize) -> Vec<Vec<usize>> {
+    (0..count)
+        .map(|_| {
+            let len = min + rng.below(max - min + 1);
+            let mut set = Vec::with_capacity(len);
+            while set.len() < len {
+                let component = rng.below(COMPONENTS);
+                if !set.contains(&component) {
+                    set.push(component);
+                }
+            }
+            set
+        })
+        .collect()
+}
+
+/// Runs `f` once untimed and `ITERATIONS` times timed, and returns the mean time per run.
+fn time(mut f: impl FnMut() -> usize) -> Duration {
+    black_box(f());
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(f());
+    }
+    start.elapsed() / ITERATIONS as u32
+}
+
+fn report(name: &str, sorted: Duration, bitset: Duration) {
+    println!(
+        "{:<12} {:>12.1} {:>12.1} {:>8.1}x",
+        name,
+        sorted.as_secs_f64() * 1_000_000.0,
+        bitset.as_secs_f64() * 1_000_000.0,
+        sorted.as_secs_f64() / bitset.as_secs_f64()
+    );
+}
+
+fn main() {
+    let ids = (0..COMPONENTS)
+        .map(|i| unsafe {
+            ComponentRegistry::register_dynamic(
+                format!("Component{}", i),
+                Layout::new::<()>(),
+                None,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mut rng = Rng::new(0x2545_F491_4F6C_DD1D);
+    let archetype_sets = random_sets(&mut rng, ARCHETYPES, 4, 16);
+    let query_sets = random_sets(&mut rng, QUERIES, 1, 4);
+
+    let build_sorted = |sets: &[Vec<usize>]| {
+        sets.iter()
+            .map(|set| {
+                let mut archetype = SortedArchetype::default();
+                set.iter()
+                    .for_each(|c| archetype.add_component_by_id(ids[*c]));
+                archetype
+            })
+            .collect::<Vec<_>>()
+    };
+    let build_bitset = |sets: &[Vec<usize>]| {
+        sets.iter()
+            .map(|set| {
+                let mut archetype = Archetype::default();
+                set.iter()
+                    .for_each(|c| archetype.add_component_by_id(ids[*c]));
+                archetype
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let (sorted_archetypes, sorted_queries) =
+        (build_sorted(&archetype_sets), build_sorted(&query_sets));
+    let (bitset_archetypes, bitset_queries) =
+        (build_bitset(&archetype_sets), build_bitset(&query_sets));
+
+    println!(
+        "{} components, {} archetypes, {} queries, {} iterations",
+        COMPONENTS, ARCHETYPES, QUERIES, ITERATIONS
+    );
+    println!(
+        "{:<12} {:>12} {:>12} {:>9}",
+        "operation", "sorted us", "bitset us", "speedup"
+    );
+
+    report(
+        "build",
+        time(|| build_sorted(&archetype_sets).len()),
+        time(|| build_bitset(&archetype_sets).len()),
+    );
+
+    // What `Query::new` does for every descriptor
+    let sorted = time(|| {
+        sorted_queries
+            .iter()
+            .map(|q| sorted_archetypes.iter().filter(|a| q.subset_of(a)).count())
+            .sum()
+    });
+    let bitset = time(|| {
+        bitset_queries
+            .iter()
+            .map(|q| bitset_archetypes.iter().filter(|a| q.subset_of(a)).count())
+            .sum()
+    });
+    report("subset_of", sorted, bitset);
+
+    // What the dispatcher does to find conflicting systems
+    let sorted = time(|| {
+        sorted_archetypes
+            .iter()
+            .map(|a| sorted_archetypes.iter().filter(|b| a.any_of(b)).count())
+            .sum()
+    });
+    let bitset = time(|| {
+        bitset_archetypes
+            .iter()
+            .map(|a| bitset_archetypes.iter().filter(|b| a.any_of(b)).count())
+            .sum()
+    });
+    report("any_of", sorted, bitset);
+}
@@ -0,0 +1,282 @@
+//! Builds dispatchers from system graph descriptions and times how long they take to run.
+//!
+//! Run with `cargo bench --bench graph -- [options] [graph files]`. When no files are provided,
+//! every graph in `benches/graphs` is run.
+//!
+//! Options:
+//! - `--threads <n>`: Number of worker threads. Defaults to the number of CPUs.
+//! - `--iterations <n>`: Number of timed runs of the dispatcher. Defaults to 1000.
+//! - `--critical-path`: Prefer systems on the critical path when scheduling.
+//!
+//! To compare the default scheduler against the critical path aware one, run the same graphs with
+//! and without `--critical-path`:
+//!
+//! ```text
+//! cargo bench --bench graph -- --threads 4 benches/graphs/chain_and_leaves.graph \
+//!     benches/graphs/uneven_chains.graph benches/graphs/bench2.graph
+//! cargo bench --bench graph -- --threads 4 --critical-path benches/graphs/chain_and_leaves.graph \
+//!     benches/graphs/uneven_chains.graph benches/graphs/bench2.graph
+//! ```
+//!
+//! `chain_and_leaves` and `uneven_chains` have long chains of dependencies that should start as
+//! early as possible. `bench2` has no dependencies, so both schedulers should perform the same.
+//!
+//! Each non-empty line of a graph file that doesn't start with `#` describes one system:
+//!
+//! ```text
+//! system <name> [write <component>...] [read <component>...] [after <system>...] [work <us>]
+//! ```
+//!
+//! Components are identified by name and don't need to be declared. Systems may only run after
+//! systems declared on an earlier line. `work` is the number of microseconds the system spends
+//! busy waiting each time it runs.
+
+use std::{
+    alloc::Layout,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use cecs::{
+    archetype::Archetype,
+    component::registry::{ComponentId, ComponentRegistry},
+    dispatcher::{Dispatcher, SystemId},
+    system::{query::QueryGenerator, System},
+    world::World,
+};
+
+/// Number of runs before timing starts.
+const WARMUP: usize = 10;
+
+/// A system that busy waits for a fixed amount of time. The data it accesses is declared when
+/// it is added to the dispatcher.
+struct GraphSystem {
+    work: Duration,
+}
+
+impl System for GraphSystem {
+    type Components = ();
+
+    fn tick(&mut self, _: QueryGenerator) {
+        let start = Instant::now();
+        while start.elapsed() < self.work {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+/// A system described by a line of a graph file.
+#[derive(Default)]
+struct SystemDesc {
+    name: String,
+    read: Archetype,
+    write: Archetype,
+    after: Vec<String>,
+    work: Duration,
+}
+
+struct Options {
+    threads: usize,
+    iterations: usize,
+    critical_path: bool,
+    graphs: Vec<PathBuf>,
+}
+
+fn parse_graph(path: &Path) -> Vec<SystemDesc> {
+    let source = fs::read_to_string(path)
+        .unwrap_or_else(|err| panic!("Unable to read {}: {}", path.display(), err));
+
+    let mut components = HashMap::<String, ComponentId>::default();
+    let mut systems = Vec::default();
+
+    for (i, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let location = format!("{}:{}", path.display(), i + 1);
+        let mut tokens = line.split_whitespace();
+        assert_eq!(
+            tokens.next(),
+            Some("system"),
+            "{}: Expected `system`",
+            location
+        );
+
+        let mut system = SystemDesc {
+            name: tokens
+                .next()
+                .unwrap_or_else(|| panic!("{}: Expected system name", location))
+                .to_string(),
+            ..SystemDesc::default()
+        };
+
+        let mut section = "";
+        for token in tokens {
+            match token {
+                "write" | "read" | "after" | "work" => section = token,
+                _ => match section {
+                    "write" | "read" => {
+                        // Components only exist to be declared as accessed, so they have no data
+                        let id = *components
+                            .entry(token.to_string())
+                            .or_insert_with(|| unsafe {
+                                ComponentRegistry::register_dynamic(
+                                    token,
+                                    Layout::new::<()>(),
+                                    None,
+                                )
+                            });
+
+                        let archetype = if section == "write" {
+                            &mut system.write
+                        } else {
+                            &mut system.read
+                        };
+                        if !archetype.contains(id) {
+                            archetype.add_component_by_id(id);
+                        }
+                    }
+                    "after" => system.after.push(token.to_string()),
+                    "work" => {
+                        let micros = token
+                            .parse()
+                            .unwrap_or_else(|_| panic!("{}: Invalid work `{}`", location, token));
+                        system.work = Duration::from_micros(micros);
+                    }
+                    _ => panic!("{}: Unexpected `{}`", location, token),
+                },
+            }
+        }
+
+        systems.push(system);
+    }
+
+    systems
+}
+
+fn build_dispatcher(systems: &[SystemDesc], options: &Options) -> Dispatcher {
+    let mut builder = Dispatcher::builder()
+        .thread_count(options.threads)
+        .critical_path(options.critical_path);
+    let mut ids = HashMap::<&str, SystemId>::default();
+
+    for system in systems {
+        let dependencies = system
+            .after
+            .iter()
+            .map(|name| {
+                *ids.get(name.as_str()).unwrap_or_else(|| {
+                    panic!("`{}` runs after unknown system `{}`", system.name, name)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let id = builder.with_system_accessing(
+            GraphSystem { work: system.work },
+            &system.read,
+            &system.write,
+            &dependencies,
+        );
+        ids.insert(&system.name, id);
+    }
+
+    builder.build()
+}
+
+fn parse_options() -> Options {
+    let mut options = Options {
+        threads: std::thread::available_parallelism()
+            .map(|threads| threads.get())
+            .unwrap_or(1),
+        iterations: 1000,
+        critical_path: false,
+        graphs: Vec::default(),
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threads" => options.threads = parse_count(args.next(), "--threads"),
+            "--iterations" => options.iterations = parse_count(args.next(), "--iterations"),
+            "--critical-path" => options.critical_path = true,
+            // Passed along by `cargo bench`
+            "--bench" => {}
+            _ => options.graphs.push(PathBuf::from(arg)),
+        }
+    }
+
+    if options.graphs.is_empty() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/graphs");
+        options.graphs = fs::read_dir(&dir)
+            .unwrap_or_else(|err| panic!("Unable to read {}: {}", dir.display(), err))
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "graph"))
+            .collect();
+        options.graphs.sort();
+    }
+
+    options
+}
+
+fn parse_count(arg: Option<String>, option: &str) -> usize {
+    arg.and_then(|arg| arg.parse().ok())
+        .filter(|count| *count != 0)
+        .unwrap_or_else(|| panic!("{} expects a positive number", option))
+}
+
+#[inline]
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000_000.0
+}
+
+fn main() {
+    let options = parse_options();
+
+    println!(
+        "{} threads, {} iterations{}",
+        options.threads,
+        options.iterations,
+        if options.critical_path {
+            ", critical path"
+        } else {
+            ""
+        }
+    );
+    println!(
+        "{:<24} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "graph", "systems", "mean us", "median us", "min us", "max us"
+    );
+
+    for path in &options.graphs {
+        let systems = parse_graph(path);
+        let mut dispatcher = build_dispatcher(&systems, &options);
+        let mut world = World::new();
+
+        for _ in 0..WARMUP {
+            dispatcher.run(&mut world);
+        }
+
+        let mut timings = Vec::with_capacity(options.iterations);
+        for _ in 0..options.iterations {
+            let start = Instant::now();
+            dispatcher.run(&mut world);
+            timings.push(start.elapsed());
+        }
+        timings.sort_unstable();
+
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        println!(
+            "{:<24} {:>8} {:>10.1} {:>10.1} {:>10.1} {:>10.1}",
+            name,
+            systems.len(),
+            micros(timings.iter().sum::<Duration>()) / timings.len() as f64,
+            micros(timings[timings.len() / 2]),
+            micros(timings[0]),
+            micros(timings[timings.len() - 1]),
+        );
+    }
+}
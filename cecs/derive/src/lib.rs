@@ -0,0 +1,243 @@
+//! Derive macros for the component traits of `cecs`. They are re-exported by `cecs` alongside the
+//! traits they implement, so there is no need to depend on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, spanned::Spanned, Data, DeriveInput, Error, Index, LitStr,
+    Member,
+};
+
+/// Implements `Component` for a type.
+///
+/// The storage kind and the registered name of the component can be set with the `component`
+/// attribute:
+///
+/// ```ignore
+/// #[derive(Component)]
+/// #[component(storage = "sparse_set", name = "Selected")]
+/// struct Selected;
+/// ```
+///
+/// The storage is either `"table"` (the default) or `"sparse_set"`.
+#[proc_macro_derive(Component, attributes(component))]
+pub fn derive_component(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    component(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Implements `Bundle` for a struct, so every field is a component of the entity the struct is
+/// spawned as.
+///
+/// Also generates a struct named after the bundle with a `Pack` suffix, which holds a vector for
+/// each field and implements `ComponentPack`. It has the same visibility as the bundle.
+#[proc_macro_derive(Bundle)]
+pub fn derive_bundle(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    bundle(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn component(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let mut storage = None;
+    let mut name = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("component"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("storage") {
+                let value = meta.value()?.parse::<LitStr>()?;
+                storage = Some(match value.value().as_str() {
+                    "table" => quote!(Table),
+                    "sparse_set" => quote!(SparseSet),
+                    _ => {
+                        return Err(Error::new(
+                            value.span(),
+                            "expected storage to be \"table\" or \"sparse_set\"",
+                        ))
+                    }
+                });
+                Ok(())
+            } else if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `storage` or `name`"))
+            }
+        })?;
+    }
+
+    let storage = storage.map(|storage| {
+        quote! {
+            const STORAGE: ::cecs::component::StorageKind =
+                ::cecs::component::StorageKind::#storage;
+        }
+    });
+    let name = name.map(|name| {
+        quote! {
+            const NAME: ::core::option::Option<&'static str> = ::core::option::Option::Some(#name);
+        }
+    });
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::cecs::component::Component for #ident #ty_generics #where_clause {
+            #storage
+            #name
+        }
+    })
+}
+
+fn bundle(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(Error::new(
+                input.span(),
+                "Bundle can only be derived for structs",
+            ))
+        }
+    };
+    if fields.is_empty() {
+        return Err(Error::new(
+            input.span(),
+            "Bundle can't be derived for a struct without fields",
+        ));
+    }
+
+    let members = fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(i)),
+        })
+        .collect::<Vec<_>>();
+    let types = fields.iter().map(|field| &field.ty).collect::<Vec<_>>();
+    let indices = (0..fields.len()).map(Index::from).collect::<Vec<_>>();
+
+    // Every field has to be a component
+    let ident = &input.ident;
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    let mut generics = input.generics.clone();
+    let where_clause = generics.make_where_clause();
+    for ty in &types {
+        where_clause
+            .predicates
+            .push(parse_quote!(#ty: ::cecs::component::Component + 'static));
+    }
+    // Duplicate components are only checked for once per bundle type
+    where_clause
+        .predicates
+        .push(parse_quote!(#ident #ty_generics: 'static));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let vis = &input.vis;
+    let pack = format_ident!("{}Pack", ident);
+    let pack_doc = format!("Vectors of the components of many `{}` bundles.", ident);
+
+    let archetype = quote!(::cecs::archetype::Archetype);
+    let archetypes = quote!(::cecs::archetype::archetypes::Archetypes);
+    let descriptor_id = quote!(::cecs::archetype::archetypes::ArchetypeDescriptorId);
+    let entity = quote!(::cecs::entity::Entity);
+    let helpers = quote!(::cecs::component::bundle);
+    let unique = quote! {
+        #helpers::assert_unique(::core::any::TypeId::of::<Self>(), || [
+            #(::cecs::component::registry::ComponentId::of::<#types>(),)*
+        ]);
+    };
+
+    Ok(quote! {
+        #[doc = #pack_doc]
+        #vis struct #pack #impl_generics (#(::std::vec::Vec<#types>,)*) #where_clause;
+
+        impl #impl_generics ::cecs::component::pack::ComponentPack for #pack #ty_generics
+            #where_clause
+        {
+            #[inline]
+            fn is_valid(&self) -> bool {
+                let len = self.0.len();
+                true #(&& self.#indices.len() == len)*
+            }
+
+            #[inline]
+            fn len(&self) -> usize {
+                assert!(self.is_valid());
+                self.0.len()
+            }
+
+            #[inline]
+            fn is_empty(&self) -> bool {
+                assert!(self.is_valid());
+                self.0.is_empty()
+            }
+
+            #[inline]
+            fn archetype(&self) -> #archetype {
+                <#ident #ty_generics as ::cecs::component::bundle::Bundle>::archetype()
+            }
+
+            fn move_into(
+                &mut self,
+                entities: &[#entity],
+                archetypes: &mut #archetypes,
+            ) -> (#descriptor_id, usize) {
+                assert!(self.is_valid());
+                assert!(entities.len() >= self.len());
+
+                #(#helpers::prepare::<#types>(archetypes);)*
+                let (id, begin) = #helpers::insert_entities(self.archetype(), entities, archetypes);
+                #(#helpers::append(&mut self.#indices, entities, id, archetypes);)*
+                (id, begin)
+            }
+        }
+
+        impl #impl_generics ::cecs::component::bundle::Bundle for #ident #ty_generics
+            #where_clause
+        {
+            type Pack = #pack #ty_generics;
+
+            #[inline]
+            fn archetype() -> #archetype {
+                let mut archetype = #archetype::default();
+                #(#helpers::add_to_archetype::<#types>(&mut archetype);)*
+                archetype
+            }
+
+            #[inline]
+            fn pack(capacity: usize) -> Self::Pack {
+                #unique
+                #pack(#(::std::vec::Vec::<#types>::with_capacity(capacity),)*)
+            }
+
+            #[inline]
+            fn push_into(self, pack: &mut Self::Pack) {
+                #(pack.#indices.push(self.#members);)*
+            }
+
+            fn move_into(
+                self,
+                entity: #entity,
+                archetypes: &mut #archetypes,
+            ) -> (#descriptor_id, usize) {
+                #unique
+
+                #(#helpers::prepare::<#types>(archetypes);)*
+                let (id, index) = #helpers::insert_entities(
+                    <Self as ::cecs::component::bundle::Bundle>::archetype(),
+                    &[entity],
+                    archetypes,
+                );
+                #(#helpers::push(self.#members, entity, id, archetypes);)*
+                (id, index)
+            }
+        }
+    })
+}
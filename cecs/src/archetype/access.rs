@@ -1,17 +1,18 @@
-use std::ptr::NonNull;
+use std::{ptr::NonNull, sync::Arc};
 
 use crate::{
     component::{
         filter::{ComponentAccess, ComponentFilter},
-        Component,
+        is_tag, Component, StorageKind,
     },
+    entity::Entity,
     prw_lock::{PrwReadHandle, PrwWriteHandle},
 };
 
 use paste::*;
 use unsafe_unwrap::*;
 
-use super::archetypes::Archetypes;
+use super::{archetypes::Archetypes, blob::BlobVec, sparse::SparseSet};
 
 /// A set of data buffers used to access components within a query.
 pub trait DataBufferSet {
@@ -24,12 +25,13 @@ pub trait DataBufferSet {
     /// Determines if the provided index points to valid components
     fn is_valid(&self, idx: usize) -> bool;
 
-    /// Fetch a filter in the set by index.
+    /// Fetch a filter in the set by index and the entity at that index. Returns `None` if the
+    /// entity is missing one of the sparse set components in the filter.
     ///
     /// # Safety
     /// No bounds checking should be performed to maximize performance. It is up to the caller to
     /// ensure the index is valid.
-    unsafe fn fetch(&mut self, idx: usize) -> Self::Filter;
+    unsafe fn fetch(&mut self, idx: usize, entity: Entity) -> Option<Self::Filter>;
 }
 
 /// A way to access a data buffer belonging to an archetype.
@@ -41,8 +43,9 @@ pub trait DataBufferAccess: Default {
     type ComponentAccess: ComponentAccess;
 
     /// Access the Nth storage buffer of the associated component type within the `Archetypes`
-    /// container.
-    fn new(archetypes: &Archetypes, idx: usize) -> Self;
+    /// container. Components with sparse set storage ignore the index, and share the access of
+    /// `previous` if provided, since their storage is locked as a whole.
+    fn new(archetypes: &Archetypes, idx: usize, previous: Option<&Self>) -> Self;
 
     fn len(&self) -> usize;
 
@@ -51,59 +54,81 @@ pub trait DataBufferAccess: Default {
     /// Determines if the provided index is valid.
     fn is_valid(&self, idx: usize) -> bool;
 
-    /// Fetch a component in the buffer by index.
+    /// Fetch a component in the buffer by index, or by entity for components with sparse set
+    /// storage. Returns `None` if the entity doesn't have a sparse set component.
     ///
     /// # Safety
     /// No bounds checking should be performed to maximize performance. It is up to the caller to
     /// ensure the index is valid.
-    unsafe fn fetch(&mut self, idx: usize) -> Self::ComponentAccess;
+    unsafe fn fetch(&mut self, idx: usize, entity: Entity) -> Option<Self::ComponentAccess>;
 }
 
 pub struct ReadDataBuffer<T> {
     #[allow(dead_code)]
-    handle: Option<PrwReadHandle<Vec<T>>>,
+    handle: Option<PrwReadHandle<BlobVec>>,
     end: NonNull<T>,
     ptr: NonNull<T>,
+    /// Set if the component has sparse set storage and the sparse set exists.
+    sparse: Option<Arc<PrwReadHandle<SparseSet<T>>>>,
 }
 
 pub struct WriteDataBuffer<T> {
     #[allow(dead_code)]
-    handle: Option<PrwWriteHandle<Vec<T>>>,
+    handle: Option<PrwWriteHandle<BlobVec>>,
     end: NonNull<T>,
     ptr: NonNull<T>,
+    /// Set if the component has sparse set storage and the sparse set exists.
+    sparse: Option<SharedSparseWrite<T>>,
 }
 
+/// Write access to a sparse set shared by every storage set of a query, along with a pointer to
+/// the data behind the handle.
+type SharedSparseWrite<T> = (Arc<PrwWriteHandle<SparseSet<T>>>, NonNull<SparseSet<T>>);
+
 impl<T: Component + 'static> DataBufferAccess for ReadDataBuffer<T> {
     type Component = T;
     type ComponentAccess = &'static Self::Component;
 
     #[inline]
-    fn new(archetypes: &Archetypes, index: usize) -> Self {
+    fn new(archetypes: &Archetypes, index: usize, previous: Option<&Self>) -> Self {
+        if T::STORAGE == StorageKind::SparseSet {
+            let sparse = match previous {
+                Some(previous) => previous.sparse.clone(),
+                None => archetypes
+                    .get_sparse_set::<T>()
+                    .map(|set| Arc::new(set.read())),
+            };
+            return Self {
+                sparse,
+                ..Self::default()
+            };
+        }
+
+        // Tags have no data buffer, and the dangling pointer is valid for zero sized types
+        if is_tag::<T>() {
+            return Self::default();
+        }
+
         let handle = archetypes
             .get_component_buffers::<Self::Component>()
             .expect("Requested non existant storage")
             .get(index);
-        let end = unsafe { handle.as_ptr().add(handle.len()) };
-        let ptr = handle.as_ptr();
+        // Slices are never null, even when empty
+        let slice = handle.as_slice::<T>();
+        let ptr = NonNull::from(slice).cast::<T>();
+        let end = unsafe { ptr.add(slice.len()) };
 
         Self {
             handle: Some(handle),
-            end: if end.is_null() {
-                unsafe { NonNull::new_unchecked(1 as *mut T) }
-            } else {
-                unsafe { NonNull::new_unchecked(end as *mut T) }
-            },
-            ptr: if ptr.is_null() {
-                unsafe { NonNull::new_unchecked(1 as *mut T) }
-            } else {
-                unsafe { NonNull::new_unchecked(ptr as *mut T) }
-            },
+            end,
+            ptr,
+            sparse: None,
         }
     }
 
     #[inline]
     fn len(&self) -> usize {
-        unsafe { self.end.as_ptr().offset_from(self.ptr.as_ptr()) as usize }
+        span(self.ptr, self.end)
     }
 
     #[inline]
@@ -113,12 +138,17 @@ impl<T: Component + 'static> DataBufferAccess for ReadDataBuffer<T> {
 
     #[inline(always)]
     fn is_valid(&self, idx: usize) -> bool {
-        unsafe { idx < self.end.as_ptr().offset_from(self.ptr.as_ptr()) as usize }
+        idx < span(self.ptr, self.end)
     }
 
     #[inline(always)]
-    unsafe fn fetch(&mut self, idx: usize) -> Self::ComponentAccess {
-        self.ptr.as_ptr().add(idx).as_ref().unsafe_unwrap()
+    unsafe fn fetch(&mut self, idx: usize, entity: Entity) -> Option<Self::ComponentAccess> {
+        if T::STORAGE == StorageKind::SparseSet {
+            let set = self.sparse.as_ref()?;
+            return set.get(entity).map(|component| &*(component as *const T));
+        }
+
+        Some(self.ptr.as_ptr().add(idx).as_ref().unsafe_unwrap())
     }
 }
 
@@ -127,8 +157,9 @@ impl<T> Default for ReadDataBuffer<T> {
     fn default() -> Self {
         Self {
             handle: None,
-            end: unsafe { NonNull::new_unchecked(1 as *mut T) },
-            ptr: unsafe { NonNull::new_unchecked(1 as *mut T) },
+            end: NonNull::dangling(),
+            ptr: NonNull::dangling(),
+            sparse: None,
         }
     }
 }
@@ -142,32 +173,48 @@ impl<T: Component + 'static> DataBufferAccess for WriteDataBuffer<T> {
     type ComponentAccess = &'static mut T;
 
     #[inline]
-    fn new(archetypes: &Archetypes, index: usize) -> Self {
+    fn new(archetypes: &Archetypes, index: usize, previous: Option<&Self>) -> Self {
+        if T::STORAGE == StorageKind::SparseSet {
+            let sparse = match previous {
+                Some(previous) => previous.sparse.clone(),
+                None => archetypes.get_sparse_set::<T>().map(|set| {
+                    let mut handle = set.write();
+                    let ptr = NonNull::from(&mut *handle);
+                    (Arc::new(handle), ptr)
+                }),
+            };
+            return Self {
+                sparse,
+                ..Self::default()
+            };
+        }
+
+        // Tags have no data buffer, and the dangling pointer is valid for zero sized types
+        if is_tag::<T>() {
+            return Self::default();
+        }
+
         let mut handle = archetypes
             .get_component_buffers::<Self::Component>()
             .expect("Requested non existant storage")
             .get_mut(index);
-        let end = unsafe { handle.as_ptr().add(handle.len()) };
-        let ptr = handle.as_mut_ptr();
+        // Slices are never null, even when empty
+        let slice = handle.as_mut_slice::<T>();
+        let len = slice.len();
+        let ptr = NonNull::from(slice).cast::<T>();
+        let end = unsafe { ptr.add(len) };
 
         Self {
             handle: Some(handle),
-            end: if end.is_null() {
-                unsafe { NonNull::new_unchecked(1 as *mut T) }
-            } else {
-                unsafe { NonNull::new_unchecked(end as *mut T) }
-            },
-            ptr: if ptr.is_null() {
-                unsafe { NonNull::new_unchecked(1 as *mut T) }
-            } else {
-                unsafe { NonNull::new_unchecked(ptr) }
-            },
+            end,
+            ptr,
+            sparse: None,
         }
     }
 
     #[inline]
     fn len(&self) -> usize {
-        unsafe { self.end.as_ptr().offset_from(self.ptr.as_ptr()) as usize }
+        span(self.ptr, self.end)
     }
 
     #[inline]
@@ -177,12 +224,20 @@ impl<T: Component + 'static> DataBufferAccess for WriteDataBuffer<T> {
 
     #[inline(always)]
     fn is_valid(&self, idx: usize) -> bool {
-        unsafe { idx < self.end.as_ptr().offset_from(self.ptr.as_ptr()) as usize }
+        idx < span(self.ptr, self.end)
     }
 
     #[inline(always)]
-    unsafe fn fetch(&mut self, idx: usize) -> Self::ComponentAccess {
-        self.ptr.as_ptr().add(idx).as_mut().unsafe_unwrap()
+    unsafe fn fetch(&mut self, idx: usize, entity: Entity) -> Option<Self::ComponentAccess> {
+        if T::STORAGE == StorageKind::SparseSet {
+            let (_, set) = self.sparse.as_mut()?;
+            return set
+                .as_mut()
+                .get_mut(entity)
+                .map(|component| &mut *(component as *mut T));
+        }
+
+        Some(self.ptr.as_ptr().add(idx).as_mut().unsafe_unwrap())
     }
 }
 
@@ -191,8 +246,9 @@ impl<T> Default for WriteDataBuffer<T> {
     fn default() -> Self {
         Self {
             handle: None,
-            end: unsafe { NonNull::new_unchecked(1 as *mut T) },
-            ptr: unsafe { NonNull::new_unchecked(1 as *mut T) },
+            end: NonNull::dangling(),
+            ptr: NonNull::dangling(),
+            sparse: None,
         }
     }
 }
@@ -201,6 +257,62 @@ unsafe impl<T> Send for WriteDataBuffer<T> {}
 
 unsafe impl<T> Sync for WriteDataBuffer<T> {}
 
+/// Number of components between two pointers into the same buffer. Always zero for tags, which
+/// have no buffer.
+#[inline(always)]
+fn span<T>(ptr: NonNull<T>, end: NonNull<T>) -> usize {
+    (end.as_ptr() as usize - ptr.as_ptr() as usize) / std::mem::size_of::<T>().max(1)
+}
+
+/// Access used by filters that only check whether an entity has a component, such as `With` and
+/// `Without`. Never touches the component's storage.
+pub struct PresenceBuffer<F> {
+    _phantom: std::marker::PhantomData<F>,
+}
+
+impl<F: ComponentAccess + Default> DataBufferAccess for PresenceBuffer<F> {
+    type Component = F::Component;
+    type ComponentAccess = F;
+
+    #[inline]
+    fn new(_: &Archetypes, _: usize, _: Option<&Self>) -> Self {
+        assert!(
+            F::Component::STORAGE == StorageKind::Table,
+            "Presence filters only support components stored within archetypes"
+        );
+        Self::default()
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        0
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        true
+    }
+
+    #[inline(always)]
+    fn is_valid(&self, _: usize) -> bool {
+        true
+    }
+
+    #[inline(always)]
+    unsafe fn fetch(&mut self, _: usize, _: Entity) -> Option<Self::ComponentAccess> {
+        Some(F::default())
+    }
+}
+
+impl<F> Default for PresenceBuffer<F> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
 macro_rules! data_buffer_set_impl {
     ( $n:expr, $( $name:ident )+ ) => {
         impl<$($name: DataBufferAccess,)*> DataBufferSet for ($($name,)*) {
@@ -222,15 +334,15 @@ macro_rules! data_buffer_set_impl {
             }
 
             #[inline(always)]
-            unsafe fn fetch(&mut self, idx: usize) -> Self::Filter {
+            unsafe fn fetch(&mut self, idx: usize, entity: Entity) -> Option<Self::Filter> {
                 paste! {
                     #[allow(non_snake_case)]
                     let ($([<$name _storage>],)*) = self;
                 }
 
-                paste! { ($(
-                    [<$name _storage>].fetch(idx),
-                )*) }
+                paste! { Some(($(
+                    [<$name _storage>].fetch(idx, entity)?,
+                )*)) }
             }
         }
     }
@@ -1,33 +1,56 @@
-use std::{any::TypeId, collections::HashMap};
+use std::{
+    any::Any,
+    collections::HashMap,
+    ops::{Add, AddAssign},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use crate::{component::Component, entity::Entity};
+use crate::{
+    component::{is_tag, registry::ComponentId, Component},
+    entity::Entity,
+    prw_lock::PrwLock,
+};
 
 use super::{
-    buffer::{DataBuffers, GenericDataBuffers},
+    buffer::{short_type_name, ComponentBuffers, DataBuffers},
+    report::StorageReport,
+    sparse::{AnySparseSet, SparseSet},
     Archetype,
 };
 
 /// Holds all of the archetype containers used in a world.
-#[derive(Default)]
 pub struct Archetypes {
+    /// Unique among every `Archetypes` object created by the process. Replaced when descriptors
+    /// are removed, since that invalidates everything known about the old descriptors.
+    id: u64,
+    /// Incremented every time descriptors are added, so caches of descriptors know when they need
+    /// to be updated.
+    generation: u64,
     /// All currently registered archetype descriptors.
     archetype_descriptors: Vec<ArchetypeDescriptor>,
     /// Maps an archetype to the ID of the descriptor that describes its data.
     to_archetype_descriptor: HashMap<Archetype, ArchetypeDescriptorId>,
     /// The containers for component data.
-    buffers: Vec<Box<dyn GenericDataBuffers>>,
-    /// Maps the type ID of a component to the `DataBuffers` object that holds components of that
-    /// type.
-    to_buffers: HashMap<TypeId, DataBuffersId>,
+    buffers: Vec<ComponentBuffers>,
+    /// Maps the ID of a component (used as the index) to the `ComponentBuffers` object that holds
+    /// components of that type.
+    to_buffers: Vec<Option<DataBuffersId>>,
     /// Data buffers for entities.
     entities: DataBuffers<Entity>,
+    /// Maps the ID of a component (used as the index) to the `PrwLock<SparseSet<T>>` that holds
+    /// components of that type, for components with sparse set storage.
+    sparse_sets: Vec<Option<Box<dyn AnySparseSet>>>,
+    /// Maps the ID of a component (used as the index) to the `PrwLock<Vec<T>>` that keeps the
+    /// values of a tag type. Entities don't store their tags, but the values they were given are
+    /// kept, so removing a tag hands back a value instead of making one up.
+    tag_values: Vec<Option<Box<dyn Any + Send + Sync>>>,
 }
 
-/// Unique ID for an archetype descriptor.
-#[derive(Debug, Copy, Clone, Default, Hash)]
+/// Unique ID for an archetype descriptor. Only changes when empty archetypes are removed.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
 pub struct ArchetypeDescriptorId(u32);
 
-/// Unique ID for a `DataBuffers` object.
+/// Unique ID for a `ComponentBuffers` object.
 #[derive(Debug, Copy, Clone, Default, Hash)]
 pub struct DataBuffersId(u32);
 
@@ -35,11 +58,49 @@ pub struct DataBuffersId(u32);
 pub struct ArchetypeDescriptor {
     /// The actual archetype being described.
     pub archetype: Archetype,
-    /// A one-to-one mapping between the component type IDs within the archetype and the index
-    /// of the `DataBuffers` that holds the components for this archetype.
-    pub map: HashMap<TypeId, usize>,
+    /// A one-to-one mapping between the component IDs within the archetype and the index of the
+    /// `ComponentBuffers` that holds the components for this archetype. Tags have no entry.
+    pub map: HashMap<ComponentId, usize>,
     /// Index of the entity data buffer.
     pub entities: usize,
+    /// Archetypes entities of this archetype move to when a component is added or removed.
+    pub edges: ArchetypeEdges,
+}
+
+/// Maps a component to the descriptor of the archetype that differs from another archetype by
+/// only that component. Filled in the first time an entity makes the move, so later moves don't
+/// need to build and hash the destination archetype.
+#[derive(Default)]
+pub struct ArchetypeEdges {
+    /// Archetypes that have one more component.
+    pub add: HashMap<ComponentId, ArchetypeDescriptorId>,
+    /// Archetypes that have one less component.
+    pub remove: HashMap<ComponentId, ArchetypeDescriptorId>,
+}
+
+/// Memory used to hold stored data, and memory allocated for it, in bytes.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub used: usize,
+    pub allocated: usize,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+impl Default for Archetypes {
+    fn default() -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            generation: 0,
+            archetype_descriptors: Vec::default(),
+            to_archetype_descriptor: HashMap::default(),
+            buffers: Vec::default(),
+            to_buffers: Vec::default(),
+            entities: DataBuffers::default(),
+            sparse_sets: Vec::default(),
+            tag_values: Vec::default(),
+        }
+    }
 }
 
 impl Archetypes {
@@ -52,6 +113,19 @@ impl Archetypes {
         &self.archetype_descriptors
     }
 
+    /// Unique ID of this container.
+    #[inline]
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Changes whenever descriptors are added. Descriptors are only ever appended, so a cache
+    /// that has seen the first `n` descriptors only needs to look at the ones after them.
+    #[inline]
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
     /// Add a new archetype descriptor to the container and return a unique ID for it.
     pub(crate) fn add_archetype(
         &mut self,
@@ -61,13 +135,146 @@ impl Archetypes {
         self.to_archetype_descriptor
             .insert(descriptor.archetype.clone(), id);
         self.archetype_descriptors.push(descriptor);
+        self.generation += 1;
         id
     }
 
+    /// Creates a descriptor for an archetype, along with a row in the data buffers of each of its
+    /// components except tags and in the entity data buffers. The data buffers of every component
+    /// must already exist.
+    pub(crate) fn create_archetype(&mut self, archetype: Archetype) -> ArchetypeDescriptorId {
+        // Describes the archetype in access conflicts
+        let label = || {
+            let mut names = archetype
+                .iter()
+                .map(|id| short_type_name(id.info().name))
+                .collect::<Vec<_>>();
+            names.sort_unstable();
+            format!("{{{}}}", names.join(", "))
+        };
+
+        let mut map = HashMap::with_capacity(archetype.len());
+        for id in archetype.iter().filter(|id| !id.info().is_tag()) {
+            let buffers = self
+                .buffers_id(id)
+                .expect("Archetype contains component without storage");
+            let buffers = &mut self.buffers[usize::from(buffers)];
+            let row = buffers.create();
+            buffers.set_label(row, &label);
+            map.insert(id, row);
+        }
+
+        let entities = self.entities.create();
+        self.entities.set_label(entities, &label);
+
+        self.add_archetype(ArchetypeDescriptor {
+            archetype,
+            map,
+            entities,
+            edges: ArchetypeEdges::default(),
+        })
+    }
+
+    #[inline]
+    pub(crate) fn descriptor(&self, id: ArchetypeDescriptorId) -> &ArchetypeDescriptor {
+        &self.archetype_descriptors[usize::from(id)]
+    }
+
+    /// Gets the archetype that has every component of archetype `from` plus component `id`,
+    /// creating it if needed. The data buffers of the component must already exist.
+    pub(crate) fn add_edge(
+        &mut self,
+        from: ArchetypeDescriptorId,
+        id: ComponentId,
+    ) -> ArchetypeDescriptorId {
+        if let Some(to) = self.descriptor(from).edges.add.get(&id) {
+            return *to;
+        }
+
+        let mut archetype = self.descriptor(from).archetype.clone();
+        archetype.add_component_by_id(id);
+        let to = self.get_or_create_archetype(archetype);
+
+        self.archetype_descriptors[usize::from(from)]
+            .edges
+            .add
+            .insert(id, to);
+        self.archetype_descriptors[usize::from(to)]
+            .edges
+            .remove
+            .insert(id, from);
+        to
+    }
+
+    /// Gets the archetype that has every component of archetype `from` except component `id`,
+    /// creating it if needed.
+    pub(crate) fn remove_edge(
+        &mut self,
+        from: ArchetypeDescriptorId,
+        id: ComponentId,
+    ) -> ArchetypeDescriptorId {
+        if let Some(to) = self.descriptor(from).edges.remove.get(&id) {
+            return *to;
+        }
+
+        let mut archetype = self.descriptor(from).archetype.clone();
+        archetype.remove_component_by_id(id);
+        let to = self.get_or_create_archetype(archetype);
+
+        self.archetype_descriptors[usize::from(from)]
+            .edges
+            .remove
+            .insert(id, to);
+        self.archetype_descriptors[usize::from(to)]
+            .edges
+            .add
+            .insert(id, from);
+        to
+    }
+
+    /// Moves the entity at `index` within archetype `from` to the end of archetype `to`, along
+    /// with every component of the entity that `to` also has. Components that only `from` has
+    /// must already have been removed the same way, by swapping in the last component.
+    ///
+    /// Returns the index of the entity within `to` and the entity that took its place within
+    /// `from`, if any.
+    pub(crate) fn move_entity(
+        &mut self,
+        index: usize,
+        from: ArchetypeDescriptorId,
+        to: ArchetypeDescriptorId,
+    ) -> (usize, Option<Entity>) {
+        let from = &self.archetype_descriptors[usize::from(from)];
+        let to = &self.archetype_descriptors[usize::from(to)];
+
+        for id in from.archetype.iter() {
+            if let Some(to_row) = to.map.get(&id) {
+                let buffers = self.to_buffers[id.index()].expect("Component storage missing");
+                self.buffers[usize::from(buffers)].move_item(from.map[&id], index, *to_row);
+            }
+        }
+
+        self.entities.move_item(from.entities, index, to.entities);
+        let swapped = self.entities.get(from.entities).get(index).copied();
+        (self.entities.get(to.entities).len() - 1, swapped)
+    }
+
+    /// Gets the descriptor of an archetype, creating it if needed. The data buffers of every
+    /// component must already exist.
+    pub(crate) fn get_or_create_archetype(
+        &mut self,
+        archetype: Archetype,
+    ) -> ArchetypeDescriptorId {
+        match self.to_archetype_descriptor.get(&archetype) {
+            Some(id) => *id,
+            None => self.create_archetype(archetype),
+        }
+    }
+
     /// Get a references to an archetype descriptor and its ID by the archetype it describes.
     ///
     /// Returns `None` if a descriptor matching the provided archetype doesn't exist.
-    pub(crate) fn get_archetype_descriptor(
+    pub fn get_archetype_descriptor(
         &self,
         archetype: &Archetype,
     ) -> Option<(&ArchetypeDescriptor, ArchetypeDescriptorId)> {
@@ -106,49 +313,309 @@ impl Archetypes {
     /// Get the data buffers for a component type.
     ///
     /// Returns `None` if data buffers for the component don't exist.
-    pub fn get_component_buffers<T: Component + 'static>(&self) -> Option<&DataBuffers<T>> {
-        if let Some(idx) = self.to_buffers.get(&TypeId::of::<T>()) {
-            self.buffers[idx.0 as usize]
-                .as_any()
-                .downcast_ref::<DataBuffers<T>>()
-        } else {
-            None
-        }
+    #[inline]
+    pub fn get_component_buffers<T: Component + 'static>(&self) -> Option<&ComponentBuffers> {
+        self.get_buffers(ComponentId::of::<T>())
     }
 
     /// Get mutable access to the data buffers for a component type.
     ///
     /// Returns `None` if data buffers for the component don't exist.
+    #[inline]
     pub fn get_component_buffers_mut<T: Component + 'static>(
         &mut self,
-    ) -> Option<&mut DataBuffers<T>> {
-        if let Some(idx) = self.to_buffers.get(&TypeId::of::<T>()) {
-            self.buffers[idx.0 as usize]
-                .as_any_mut()
-                .downcast_mut::<DataBuffers<T>>()
+    ) -> Option<&mut ComponentBuffers> {
+        self.get_buffers_mut(ComponentId::of::<T>())
+    }
+
+    /// Create data buffers for a component type, or the storage for its values if it is a tag.
+    /// Should do nothing if they already exist.
+    #[inline]
+    pub fn create_component_buffers<T: Component + 'static>(&mut self) {
+        if is_tag::<T>() {
+            self.create_tag_values::<T>();
         } else {
-            None
+            self.create_buffers(ComponentId::of::<T>());
         }
     }
 
-    /// Create data buffers for a component type. Should do nothing if data buffers for the
-    /// component type already exist.
-    pub fn create_component_buffers<T: Component + 'static>(&mut self) {
+    /// Get the data buffers for a component.
+    ///
+    /// Returns `None` if data buffers for the component don't exist.
+    #[inline]
+    pub fn get_buffers(&self, id: ComponentId) -> Option<&ComponentBuffers> {
+        self.buffers_id(id)
+            .map(|idx| &self.buffers[usize::from(idx)])
+    }
+
+    /// Get mutable access to the data buffers for a component.
+    ///
+    /// Returns `None` if data buffers for the component don't exist.
+    #[inline]
+    pub fn get_buffers_mut(&mut self, id: ComponentId) -> Option<&mut ComponentBuffers> {
+        self.buffers_id(id)
+            .map(|idx| &mut self.buffers[usize::from(idx)])
+    }
+
+    /// Create data buffers for a component. Should do nothing if data buffers for the component
+    /// already exist or if the component is a tag.
+    pub fn create_buffers(&mut self, id: ComponentId) {
+        // Tags have nothing to store
+        let info = id.info();
+        if info.is_tag() {
+            return;
+        }
+
         // Step 1: Check to see if `self.buffers` already contains a buffer of the appropriate
         // type. If it does, do nothing.
-        let t = TypeId::of::<T>();
-        if self.to_buffers.contains_key(&t) {
+        if self.buffers_id(id).is_some() {
             return;
         }
 
-        // Step 2: Create a `DataBuffers<T>` instance and put it into `self.buffers`.
-        let new_data: DataBuffers<T> = DataBuffers::default();
+        // Step 2: Create a `ComponentBuffers` instance and put it into `self.buffers`.
         let location = DataBuffersId::from(self.buffers.len());
-        self.buffers.push(Box::new(new_data));
+        self.buffers.push(ComponentBuffers::new(info));
+
+        // Step 3: Update `self.to_buffers` at the index of the component ID with the index of
+        // the newly created buffer in `self.buffers`.
+        if self.to_buffers.len() <= id.index() {
+            self.to_buffers.resize(id.index() + 1, None);
+        }
+        self.to_buffers[id.index()] = Some(location);
+    }
+
+    /// Get the sparse set for a component type.
+    ///
+    /// Returns `None` if the sparse set for the component doesn't exist.
+    pub fn get_sparse_set<T: Component + 'static>(&self) -> Option<&PrwLock<SparseSet<T>>> {
+        self.sparse_sets
+            .get(ComponentId::of::<T>().index())?
+            .as_ref()?
+            .as_any()
+            .downcast_ref()
+    }
+
+    /// Get mutable access to the sparse set for a component type.
+    ///
+    /// Returns `None` if the sparse set for the component doesn't exist.
+    pub fn get_sparse_set_mut<T: Component + 'static>(
+        &mut self,
+    ) -> Option<&mut PrwLock<SparseSet<T>>> {
+        self.sparse_sets
+            .get_mut(ComponentId::of::<T>().index())?
+            .as_mut()?
+            .as_any_mut()
+            .downcast_mut()
+    }
+
+    /// Create the sparse set for a component type, returning it. If it already exists, the
+    /// existing sparse set is returned.
+    pub fn create_sparse_set<T: Component + 'static>(&mut self) -> &mut PrwLock<SparseSet<T>> {
+        let id = ComponentId::of::<T>();
+        if self.sparse_sets.len() <= id.index() {
+            self.sparse_sets.resize_with(id.index() + 1, || None);
+        }
+        self.sparse_sets[id.index()]
+            .get_or_insert_with(|| Box::new(PrwLock::new(SparseSet::<T>::default())))
+            .as_any_mut()
+            .downcast_mut()
+            .expect("Sparse set has the wrong type")
+    }
+
+    /// Get the values kept for a tag type, one for every entity that has the tag.
+    ///
+    /// Returns `None` if the storage for the values doesn't exist.
+    pub fn get_tag_values<T: Component + 'static>(&self) -> Option<&PrwLock<Vec<T>>> {
+        self.tag_values
+            .get(ComponentId::of::<T>().index())?
+            .as_ref()?
+            .downcast_ref()
+    }
+
+    /// Create the storage for the values of a tag type, returning it. If it already exists, the
+    /// existing storage is returned.
+    pub fn create_tag_values<T: Component + 'static>(&mut self) -> &mut PrwLock<Vec<T>> {
+        let id = ComponentId::of::<T>();
+        if self.tag_values.len() <= id.index() {
+            self.tag_values.resize_with(id.index() + 1, || None);
+        }
+        self.tag_values[id.index()]
+            .get_or_insert_with(|| Box::new(PrwLock::new(Vec::<T>::new())))
+            .downcast_mut()
+            .expect("Tag values have the wrong type")
+    }
+
+    /// Frees any memory that isn't needed to hold the current entities and components.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        for buffers in &mut self.buffers {
+            buffers.shrink_to_fit();
+        }
+        for set in self.sparse_sets.iter_mut().flatten() {
+            set.shrink_to_fit();
+        }
+        self.entities.shrink_to_fit();
+    }
+
+    /// Removes every archetype without entities, along with the data buffers of its components.
+    /// The remaining descriptors keep their order, but their IDs and the rows of their data
+    /// buffers change, so every cache of descriptors is invalidated.
+    ///
+    /// Returns the new ID of each descriptor, indexed by its old ID, or `None` if it was removed.
+    pub(crate) fn remove_empty(&mut self) -> Vec<Option<ArchetypeDescriptorId>> {
+        let keep = self
+            .archetype_descriptors
+            .iter()
+            .map(|descriptor| !self.entities.get(descriptor.entities).is_empty())
+            .collect::<Vec<_>>();
+
+        let mut next = 0;
+        let remap = keep
+            .iter()
+            .map(|keep| {
+                keep.then(|| {
+                    next += 1;
+                    ArchetypeDescriptorId(next - 1)
+                })
+            })
+            .collect::<Vec<_>>();
+        if keep.iter().all(|keep| *keep) {
+            return remap;
+        }
 
-        // Step 3: Update `self.to_buffers` with a key of `TypeId::of<T>()` and a value of the
-        // index of the newly created buffer in `self.buffers`.
-        self.to_buffers.insert(t, location);
+        // Drop the rows of removed archetypes from every data buffer
+        let mut entity_rows = vec![false; keep.len()];
+        let mut component_rows = self
+            .buffers
+            .iter()
+            .map(|buffers| vec![false; buffers.len()])
+            .collect::<Vec<_>>();
+        for (descriptor, _) in self
+            .archetype_descriptors
+            .iter()
+            .zip(&keep)
+            .filter(|(_, k)| **k)
+        {
+            entity_rows[descriptor.entities] = true;
+            for (id, row) in &descriptor.map {
+                component_rows[usize::from(self.to_buffers[id.index()].unwrap())][*row] = true;
+            }
+        }
+        let entity_rows = self.entities.retain(&entity_rows);
+        let component_rows = self
+            .buffers
+            .iter_mut()
+            .zip(&component_rows)
+            .map(|(buffers, keep)| buffers.retain(keep))
+            .collect::<Vec<_>>();
+
+        let descriptors = std::mem::take(&mut self.archetype_descriptors);
+        self.to_archetype_descriptor.clear();
+        for (mut descriptor, _) in descriptors.into_iter().zip(&keep).filter(|(_, k)| **k) {
+            descriptor.entities = entity_rows[descriptor.entities].unwrap();
+            for (id, row) in &mut descriptor.map {
+                let buffers = usize::from(self.to_buffers[id.index()].unwrap());
+                *row = component_rows[buffers][*row].unwrap();
+            }
+
+            // Edges to removed archetypes are found again the next time they are needed
+            for edges in [&mut descriptor.edges.add, &mut descriptor.edges.remove] {
+                edges.retain(|_, to| remap[usize::from(*to)].is_some());
+                for to in edges.values_mut() {
+                    *to = remap[usize::from(*to)].unwrap();
+                }
+            }
+
+            let id = ArchetypeDescriptorId(self.archetype_descriptors.len() as u32);
+            self.to_archetype_descriptor
+                .insert(descriptor.archetype.clone(), id);
+            self.archetype_descriptors.push(descriptor);
+        }
+
+        self.id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        self.generation += 1;
+        remap
+    }
+
+    /// Memory used by the entities and components of each archetype, in descriptor order.
+    /// Components with sparse set storage aren't part of any archetype.
+    pub fn memory_by_archetype(&self) -> Vec<(&Archetype, MemoryUsage)> {
+        self.archetype_descriptors
+            .iter()
+            .map(|descriptor| {
+                let mut usage = self.entities.memory_usage(descriptor.entities);
+                for (id, row) in &descriptor.map {
+                    usage += self.get_buffers(*id).unwrap().memory_usage(*row);
+                }
+                (&descriptor.archetype, usage)
+            })
+            .collect()
+    }
+
+    /// Memory used by the components of each type with storage, ordered by component ID.
+    pub fn memory_by_component(&self) -> Vec<(ComponentId, MemoryUsage)> {
+        let buffers = self.to_buffers.iter().enumerate().filter_map(|(i, idx)| {
+            let buffers = &self.buffers[usize::from((*idx)?)];
+            let usage = (0..buffers.len())
+                .map(|row| buffers.memory_usage(row))
+                .fold(MemoryUsage::default(), Add::add);
+            Some((ComponentId::from_index(i), usage))
+        });
+        let sparse_sets = self.sparse_sets.iter().enumerate().filter_map(|(i, set)| {
+            Some((ComponentId::from_index(i), set.as_ref()?.memory_usage()))
+        });
+
+        let mut usage = buffers.chain(sparse_sets).collect::<Vec<_>>();
+        usage.sort_unstable_by_key(|(id, _)| *id);
+        usage
+    }
+
+    /// Number of components in each sparse set, ordered by component ID.
+    pub(crate) fn sparse_set_lens(&self) -> Vec<(ComponentId, usize)> {
+        self.sparse_sets
+            .iter()
+            .enumerate()
+            .filter_map(|(i, set)| Some((ComponentId::from_index(i), set.as_ref()?.len())))
+            .collect()
+    }
+
+    /// Describes every archetype and component in the container.
+    pub fn report(&self) -> StorageReport {
+        StorageReport::new(self)
+    }
+
+    #[inline]
+    fn buffers_id(&self, id: ComponentId) -> Option<DataBuffersId> {
+        self.to_buffers.get(id.index()).copied().flatten()
+    }
+}
+
+impl MemoryUsage {
+    /// Memory used and allocated by a vector.
+    #[inline]
+    pub fn of<T>(items: &Vec<T>) -> Self {
+        Self {
+            used: std::mem::size_of_val(items.as_slice()),
+            allocated: items.capacity() * std::mem::size_of::<T>(),
+        }
+    }
+}
+
+impl Add for MemoryUsage {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, other: Self) -> Self {
+        Self {
+            used: self.used + other.used,
+            allocated: self.allocated + other.allocated,
+        }
+    }
+}
+
+impl AddAssign for MemoryUsage {
+    #[inline]
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
     }
 }
 
@@ -212,7 +679,7 @@ impl From<DataBuffersId> for usize {
 mod tests {
     use std::collections::HashMap;
 
-    use super::{Archetype, ArchetypeDescriptor, Archetypes};
+    use super::{Archetype, ArchetypeDescriptor, ArchetypeEdges, Archetypes};
 
     #[test]
     fn arch_descript_test() {
@@ -224,6 +691,7 @@ mod tests {
             archetype: Archetype::default(),
             map: HashMap::default(),
             entities: 1 as usize,
+            edges: ArchetypeEdges::default(),
         });
 
         let a = one.get_archetype_descriptor(&arc);
@@ -0,0 +1,368 @@
+use std::{
+    alloc::{self, Layout},
+    any::TypeId,
+    ptr::NonNull,
+};
+
+use crate::component::registry::ComponentInfo;
+
+/// A growable array of components whose type is only known at runtime, through the layout and
+/// drop function of the component. Typed access checks the type once per call, so working with
+/// slices of the array is as fast as working with a `Vec<T>`.
+pub struct BlobVec {
+    info: &'static ComponentInfo,
+    /// Dangling but aligned if nothing is allocated.
+    data: NonNull<u8>,
+    len: usize,
+    capacity: usize,
+}
+
+// SAFETY: The registry only registers `Send + Sync` types, and the safety contract of
+// `ComponentRegistry::register_dynamic` requires the same of dynamic components. Typed values
+// are only accepted if they have the type of the components.
+unsafe impl Send for BlobVec {}
+unsafe impl Sync for BlobVec {}
+
+impl BlobVec {
+    pub fn new(info: &'static ComponentInfo) -> Self {
+        let zero_sized = info.layout.size() == 0;
+        Self {
+            info,
+            data: dangling(info.layout),
+            len: 0,
+            // Zero sized components never need an allocation
+            capacity: if zero_sized { usize::MAX } else { 0 },
+        }
+    }
+
+    #[inline]
+    pub fn info(&self) -> &'static ComponentInfo {
+        self.info
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Pointer to the first component. Dangling if there are none.
+    #[inline]
+    pub fn as_ptr(&self) -> *const u8 {
+        self.data.as_ptr()
+    }
+
+    /// Mutable pointer to the first component. Dangling if there are none.
+    #[inline]
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.data.as_ptr()
+    }
+
+    /// Pointer to the component at `index`.
+    ///
+    /// Panics if the index is out of bounds.
+    #[inline]
+    pub fn get_ptr(&self, index: usize) -> *const u8 {
+        assert!(index < self.len, "Component index out of bounds");
+        unsafe { self.ptr_at(index) }
+    }
+
+    /// Mutable pointer to the component at `index`.
+    ///
+    /// Panics if the index is out of bounds.
+    #[inline]
+    pub fn get_mut_ptr(&mut self, index: usize) -> *mut u8 {
+        assert!(index < self.len, "Component index out of bounds");
+        unsafe { self.ptr_at(index) }
+    }
+
+    /// Views the components as a slice of their type.
+    ///
+    /// Panics if `T` isn't the type of the components.
+    #[inline]
+    pub fn as_slice<T: 'static>(&self) -> &[T] {
+        self.assert_type::<T>();
+        unsafe { std::slice::from_raw_parts(self.data.as_ptr().cast(), self.len) }
+    }
+
+    /// Views the components as a mutable slice of their type.
+    ///
+    /// Panics if `T` isn't the type of the components.
+    #[inline]
+    pub fn as_mut_slice<T: 'static>(&mut self) -> &mut [T] {
+        self.assert_type::<T>();
+        unsafe { std::slice::from_raw_parts_mut(self.data.as_ptr().cast(), self.len) }
+    }
+
+    /// Panics if `T` isn't the type of the components.
+    #[inline]
+    pub fn push<T: Send + Sync + 'static>(&mut self, component: T) {
+        self.assert_type::<T>();
+        let component = std::mem::ManuallyDrop::new(component);
+        unsafe { self.push_raw((&*component as *const T).cast()) };
+    }
+
+    /// Moves every component out of `components` and onto the end of the array.
+    ///
+    /// Panics if `T` isn't the type of the components.
+    pub fn append<T: Send + Sync + 'static>(&mut self, components: &mut Vec<T>) {
+        self.assert_type::<T>();
+        self.reserve(components.len());
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                components.as_ptr(),
+                self.ptr_at(self.len).cast::<T>(),
+                components.len(),
+            );
+            self.len += components.len();
+            components.set_len(0);
+        }
+    }
+
+    /// Copies a component onto the end of the array, taking ownership of it.
+    ///
+    /// # Safety
+    /// `component` must point to a valid component of the array's type, which must not be used or
+    /// dropped afterwards.
+    pub unsafe fn push_raw(&mut self, component: *const u8) {
+        self.reserve(1);
+        std::ptr::copy_nonoverlapping(component, self.ptr_at(self.len), self.info.layout.size());
+        self.len += 1;
+    }
+
+    /// Removes the component at `index`, replacing it with the last component, and returns it.
+    ///
+    /// Panics if `T` isn't the type of the components or if the index is out of bounds.
+    pub fn swap_remove<T: 'static>(&mut self, index: usize) -> T {
+        self.assert_type::<T>();
+        assert!(index < self.len, "Component index out of bounds");
+        unsafe {
+            let component = self.ptr_at(index).cast::<T>().read();
+            self.fill_gap(index);
+            component
+        }
+    }
+
+    /// Removes and drops the component at `index`, replacing it with the last component.
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn swap_remove_and_drop(&mut self, index: usize) {
+        assert!(index < self.len, "Component index out of bounds");
+        unsafe {
+            if let Some(drop) = self.info.drop {
+                drop(self.ptr_at(index));
+            }
+            self.fill_gap(index);
+        }
+    }
+
+    /// Removes the component at `index`, replacing it with the last component, and pushes it onto
+    /// the end of `other`.
+    ///
+    /// Panics if the index is out of bounds or if the arrays hold different components.
+    pub fn swap_remove_into(&mut self, index: usize, other: &mut BlobVec) {
+        assert!(index < self.len, "Component index out of bounds");
+        assert_eq!(
+            self.info.id, other.info.id,
+            "Moved between different components"
+        );
+        unsafe {
+            other.push_raw(self.ptr_at(index));
+            self.fill_gap(index);
+        }
+    }
+
+    /// Drops every component.
+    pub fn clear(&mut self) {
+        let len = self.len;
+        // Components are forgotten instead of dropped twice if a drop panics
+        self.len = 0;
+        if let Some(drop) = self.info.drop {
+            for i in 0..len {
+                unsafe { drop(self.ptr_at(i)) };
+            }
+        }
+    }
+
+    /// Ensures there is room for `additional` more components.
+    pub fn reserve(&mut self, additional: usize) {
+        let required = self.len.checked_add(additional).expect("Capacity overflow");
+        if required <= self.capacity {
+            return;
+        }
+
+        let capacity = required.max(self.capacity * 2).max(4);
+        let layout = array_layout(self.info.layout, capacity);
+        let data = unsafe {
+            if self.capacity == 0 {
+                alloc::alloc(layout)
+            } else {
+                alloc::realloc(
+                    self.data.as_ptr(),
+                    array_layout(self.info.layout, self.capacity),
+                    layout.size(),
+                )
+            }
+        };
+
+        self.data = NonNull::new(data).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        self.capacity = capacity;
+    }
+
+    /// Reduces the capacity to the number of components.
+    pub fn shrink_to_fit(&mut self) {
+        if self.info.layout.size() == 0 || self.capacity == self.len {
+            return;
+        }
+
+        unsafe {
+            let old = array_layout(self.info.layout, self.capacity);
+            if self.len == 0 {
+                alloc::dealloc(self.data.as_ptr(), old);
+                self.data = dangling(self.info.layout);
+            } else {
+                let layout = array_layout(self.info.layout, self.len);
+                let data = alloc::realloc(self.data.as_ptr(), old, layout.size());
+                self.data = NonNull::new(data).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+            }
+        }
+        self.capacity = self.len;
+    }
+
+    /// Number of components that fit without reallocating.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[inline(always)]
+    unsafe fn ptr_at(&self, index: usize) -> *mut u8 {
+        self.data.as_ptr().add(index * self.info.layout.size())
+    }
+
+    /// Moves the last component into the slot at `index`, whose component must already have been
+    /// moved out or dropped.
+    #[inline]
+    unsafe fn fill_gap(&mut self, index: usize) {
+        let last = self.len - 1;
+        if index != last {
+            std::ptr::copy_nonoverlapping(
+                self.ptr_at(last),
+                self.ptr_at(index),
+                self.info.layout.size(),
+            );
+        }
+        self.len = last;
+    }
+
+    #[inline]
+    fn assert_type<T: 'static>(&self) {
+        assert!(
+            self.info.type_id == Some(TypeId::of::<T>()),
+            "Accessed components of type {} as {}",
+            self.info.name,
+            std::any::type_name::<T>()
+        );
+    }
+}
+
+impl Drop for BlobVec {
+    fn drop(&mut self) {
+        self.clear();
+        if self.info.layout.size() != 0 && self.capacity != 0 {
+            unsafe {
+                alloc::dealloc(
+                    self.data.as_ptr(),
+                    array_layout(self.info.layout, self.capacity),
+                )
+            };
+        }
+    }
+}
+
+/// Layout of an array of `n` items with the given layout. A layout's size is always a multiple of
+/// its alignment, so there is no padding between items.
+#[inline]
+fn array_layout(item: Layout, n: usize) -> Layout {
+    let size = item.size().checked_mul(n).expect("Capacity overflow");
+    Layout::from_size_align(size, item.align()).expect("Capacity overflow")
+}
+
+#[inline]
+fn dangling(layout: Layout) -> NonNull<u8> {
+    // The alignment is never zero
+    unsafe { NonNull::new_unchecked(layout.align() as *mut u8) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::BlobVec;
+    use crate::component::registry::ComponentRegistry;
+
+    #[test]
+    fn typed_and_raw_access() {
+        let mut a = BlobVec::new(ComponentRegistry::id::<(u8, u64)>().info());
+        let mut b = BlobVec::new(ComponentRegistry::id::<(u8, u64)>().info());
+
+        for i in 0..10u8 {
+            a.push((i, i as u64 * 100));
+        }
+        a.append(&mut vec![(10u8, 1000u64), (11, 1100)]);
+        assert_eq!(a.len(), 12);
+
+        a.swap_remove_into(2, &mut b);
+        assert_eq!(b.as_slice::<(u8, u64)>(), &[(2, 200)]);
+        assert_eq!(a.as_slice::<(u8, u64)>()[2], (11, 1100));
+        assert_eq!(a.swap_remove::<(u8, u64)>(0), (0, 0));
+        assert_eq!(a.len(), 10);
+
+        a.as_mut_slice::<(u8, u64)>()[1].0 = 42;
+        assert_eq!(unsafe { *a.get_ptr(1) }, 42);
+
+        a.shrink_to_fit();
+        assert_eq!(a.capacity(), a.len());
+    }
+
+    #[test]
+    fn components_are_dropped() {
+        let shared = Arc::new(());
+        let mut blob = BlobVec::new(ComponentRegistry::id::<Arc<()>>().info());
+        for _ in 0..5 {
+            blob.push(shared.clone());
+        }
+        assert_eq!(Arc::strong_count(&shared), 6);
+
+        blob.swap_remove_and_drop(1);
+        assert_eq!(Arc::strong_count(&shared), 5);
+        drop(blob.swap_remove::<Arc<()>>(0));
+        assert_eq!(Arc::strong_count(&shared), 4);
+
+        drop(blob);
+        assert_eq!(Arc::strong_count(&shared), 1);
+    }
+
+    #[test]
+    fn zero_sized_components() {
+        struct Tag;
+
+        let mut blob = BlobVec::new(ComponentRegistry::id::<Tag>().info());
+        for _ in 0..3 {
+            blob.push(Tag);
+        }
+        blob.swap_remove_and_drop(0);
+        assert_eq!(blob.as_slice::<Tag>().len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Accessed components of type")]
+    fn wrong_type() {
+        let blob = BlobVec::new(ComponentRegistry::id::<u32>().info());
+        blob.as_slice::<i32>();
+    }
+}
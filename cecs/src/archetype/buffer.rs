@@ -1,26 +1,39 @@
-use std::any::Any;
+use crate::{
+    component::registry::ComponentInfo,
+    prw_lock::{Holder, PrwLock, PrwReadHandle, PrwWriteHandle},
+    system::current_system,
+};
 
-use crate::prw_lock::{PrwLock, PrwReadHandle, PrwWriteHandle};
+use super::{archetypes::MemoryUsage, blob::BlobVec};
 
 /// Holds lists of objects of a single type. The `Archetypes` uses these to allocate memory for
-/// components and entities.
+/// entities.
 pub struct DataBuffers<T: Send + Sync> {
-    buffers: Vec<PrwLock<Vec<T>>>,
+    rows: Rows<Vec<T>>,
 }
 
-pub trait GenericDataBuffers: Send + Sync {
-    fn as_any(&self) -> &dyn Any;
-
-    fn as_any_mut(&mut self) -> &mut dyn Any;
+/// Holds lists of components of a single type, one for each archetype containing the component.
+/// The components are stored without their type, so archetypes can move and drop them without
+/// knowing it.
+pub struct ComponentBuffers {
+    info: &'static ComponentInfo,
+    rows: Rows<BlobVec>,
+}
 
-    /// Allocates a new row of component storage and returns the index of the row.
-    fn create(&mut self) -> usize;
+/// Locked buffers, one for each archetype, shared by `DataBuffers` and `ComponentBuffers` so both
+/// lock and explain access conflicts the same way.
+struct Rows<B> {
+    buffers: Vec<PrwLock<B>>,
+    /// Describes the archetype each buffer belongs to. Only kept in debug builds, where it is used
+    /// to explain access conflicts.
+    #[cfg(debug_assertions)]
+    labels: Vec<String>,
 }
 
 impl<T: Send + Sync> Default for DataBuffers<T> {
     fn default() -> Self {
         DataBuffers {
-            buffers: Vec::default(),
+            rows: Rows::default(),
         }
     }
 }
@@ -33,7 +46,7 @@ impl<T: Send + Sync> DataBuffers<T> {
     /// is invalid.
     #[inline]
     pub fn get(&self, i: usize) -> PrwReadHandle<Vec<T>> {
-        self.buffers[i].read()
+        self.rows.get(i, std::any::type_name::<T>())
     }
 
     /// Requests mutable access to a buffer within the container.
@@ -43,24 +56,318 @@ impl<T: Send + Sync> DataBuffers<T> {
     /// buffer index is invalid.
     #[inline]
     pub fn get_mut(&self, i: usize) -> PrwWriteHandle<Vec<T>> {
-        self.buffers[i].write()
+        self.rows.get_mut(i, std::any::type_name::<T>())
+    }
+
+    /// Allocates a new buffer and returns its index.
+    #[inline]
+    pub(crate) fn create(&mut self) -> usize {
+        self.rows.push(Vec::default())
+    }
+
+    /// Sets the description of the archetype a buffer belongs to, which is used in the panic
+    /// message of access conflicts. Does nothing in release builds.
+    #[inline]
+    pub(crate) fn set_label(&mut self, i: usize, label: &dyn Fn() -> String) {
+        self.rows.set_label(i, label);
+    }
+
+    /// Removes the object at `index` of buffer `from`, replacing it with the last object of that
+    /// buffer, and pushes it onto buffer `to`.
+    pub(crate) fn move_item(&mut self, from: usize, index: usize, to: usize) {
+        let item = self.get_mut(from).swap_remove(index);
+        self.get_mut(to).push(item);
+    }
+
+    /// Memory used and allocated by a buffer.
+    #[inline]
+    pub fn memory_usage(&self, i: usize) -> MemoryUsage {
+        MemoryUsage::of(&*self.get(i))
+    }
+
+    /// Frees any memory the buffers don't need to hold their current objects.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.rows
+            .shrink_to_fit(std::any::type_name::<T>(), Vec::shrink_to_fit);
+    }
+
+    /// Drops every buffer whose index is `false` within `keep`. Returns the new index of each
+    /// buffer, or `None` if it was dropped. Buffers keep their order.
+    pub(crate) fn retain(&mut self, keep: &[bool]) -> Vec<Option<usize>> {
+        self.rows.retain(keep)
+    }
+}
+
+impl ComponentBuffers {
+    pub fn new(info: &'static ComponentInfo) -> Self {
+        Self {
+            info,
+            rows: Rows::default(),
+        }
+    }
+
+    /// Describes the type of the components.
+    #[inline]
+    pub fn info(&self) -> &'static ComponentInfo {
+        self.info
+    }
+
+    /// Number of buffers in the container.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.rows.buffers.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.rows.buffers.is_empty()
+    }
+
+    /// Requests immutable access to a buffer within the container.
+    ///
+    /// # Panic
+    /// Should panic if the buffer is currently being written to or if the provided buffer index
+    /// is invalid.
+    #[inline]
+    pub fn get(&self, i: usize) -> PrwReadHandle<BlobVec> {
+        self.rows.get(i, self.info.name)
+    }
+
+    /// Requests mutable access to a buffer within the container.
+    ///
+    /// # Panic
+    /// Should panic if the buffer is currently being read from or written to or if the provided
+    /// buffer index is invalid.
+    #[inline]
+    pub fn get_mut(&self, i: usize) -> PrwWriteHandle<BlobVec> {
+        self.rows.get_mut(i, self.info.name)
+    }
+
+    /// Allocates a new buffer and returns its index.
+    #[inline]
+    pub(crate) fn create(&mut self) -> usize {
+        self.rows.push(BlobVec::new(self.info))
+    }
+
+    /// Sets the description of the archetype a buffer belongs to, which is used in the panic
+    /// message of access conflicts. Does nothing in release builds.
+    #[inline]
+    pub(crate) fn set_label(&mut self, i: usize, label: &dyn Fn() -> String) {
+        self.rows.set_label(i, label);
+    }
+
+    /// Removes the component at `index` of buffer `from`, replacing it with the last component of
+    /// that buffer, and pushes it onto buffer `to`.
+    pub(crate) fn move_item(&mut self, from: usize, index: usize, to: usize) {
+        self.get_mut(from)
+            .swap_remove_into(index, &mut self.get_mut(to));
+    }
+
+    /// Memory used and allocated by a buffer.
+    #[inline]
+    pub fn memory_usage(&self, i: usize) -> MemoryUsage {
+        let buffer = self.get(i);
+        let size = self.info.layout.size();
+        MemoryUsage {
+            used: buffer.len() * size,
+            allocated: buffer.capacity() * size,
+        }
+    }
+
+    /// Frees any memory the buffers don't need to hold their current components.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.rows
+            .shrink_to_fit(self.info.name, BlobVec::shrink_to_fit);
+    }
+
+    /// Drops every buffer whose index is `false` within `keep`, along with its components.
+    /// Returns the new index of each buffer, or `None` if it was dropped. Buffers keep their
+    /// order.
+    pub(crate) fn retain(&mut self, keep: &[bool]) -> Vec<Option<usize>> {
+        self.rows.retain(keep)
+    }
+}
+
+impl<B> Default for Rows<B> {
+    fn default() -> Self {
+        Self {
+            buffers: Vec::default(),
+            #[cfg(debug_assertions)]
+            labels: Vec::default(),
+        }
     }
 }
 
-impl<T: Send + Sync + 'static> GenericDataBuffers for DataBuffers<T> {
+impl<B> Rows<B> {
+    /// Panics with a conflict naming `type_name` if the buffer is being written to.
     #[inline]
-    fn as_any(&self) -> &dyn Any {
-        self
+    fn get(&self, i: usize, type_name: &str) -> PrwReadHandle<B> {
+        match self.buffers[i].try_read() {
+            Ok(handle) => handle,
+            Err(_) => self.conflict(i, type_name, false),
+        }
     }
 
+    /// Panics with a conflict naming `type_name` if the buffer is being read from or written to.
     #[inline]
-    fn as_any_mut(&mut self) -> &mut dyn Any {
-        self
+    fn get_mut(&self, i: usize, type_name: &str) -> PrwWriteHandle<B> {
+        match self.buffers[i].try_write() {
+            Ok(handle) => handle,
+            Err(_) => self.conflict(i, type_name, true),
+        }
     }
 
+    /// Adds a buffer and returns its index.
     #[inline]
-    fn create(&mut self) -> usize {
-        self.buffers.push(PrwLock::new(Vec::default()));
+    fn push(&mut self, buffer: B) -> usize {
+        self.buffers.push(PrwLock::new(buffer));
+        #[cfg(debug_assertions)]
+        self.labels.push(String::default());
         self.buffers.len() - 1
     }
+
+    #[inline]
+    fn set_label(&mut self, _i: usize, _label: &dyn Fn() -> String) {
+        #[cfg(debug_assertions)]
+        {
+            self.labels[_i] = _label();
+        }
+    }
+
+    fn shrink_to_fit(&mut self, type_name: &str, shrink: impl Fn(&mut B)) {
+        for i in 0..self.buffers.len() {
+            shrink(&mut self.get_mut(i, type_name));
+        }
+        self.buffers.shrink_to_fit();
+    }
+
+    fn retain(&mut self, keep: &[bool]) -> Vec<Option<usize>> {
+        #[cfg(debug_assertions)]
+        retain_by_index(&mut self.labels, keep);
+        retain_by_index(&mut self.buffers, keep)
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn conflict(&self, i: usize, type_name: &str, write: bool) -> ! {
+        #[cfg(debug_assertions)]
+        let archetype = self.labels[i].as_str();
+        #[cfg(not(debug_assertions))]
+        let archetype = "{?}";
+
+        conflict(self.buffers[i].holders(), type_name, archetype, write)
+    }
+}
+
+/// Drops every item whose index is `false` within `keep`, returning the new index of each item.
+fn retain_by_index<T>(items: &mut Vec<T>, keep: &[bool]) -> Vec<Option<usize>> {
+    let mut next = 0;
+    let remap = keep
+        .iter()
+        .map(|keep| {
+            keep.then(|| {
+                next += 1;
+                next - 1
+            })
+        })
+        .collect();
+
+    let mut keep = keep.iter();
+    items.retain(|_| *keep.next().unwrap());
+    remap
+}
+
+/// Panics with a message describing who was denied access to a buffer, and who holds it.
+#[cold]
+#[inline(never)]
+fn conflict(holders: Vec<Holder>, type_name: &str, archetype: &str, write: bool) -> ! {
+    let describe = |system: Option<&str>| match system {
+        Some(system) => format!("System {}", short_type_name(system)),
+        None => String::from("Code outside of a system"),
+    };
+    let mode = |write: bool| if write { "Write" } else { "Read" };
+
+    let holders = holders
+        .into_iter()
+        .map(|holder| format!("{} holds {}", describe(holder.system), mode(holder.write)))
+        .collect::<Vec<_>>();
+
+    panic!(
+        "{} requested {}<{}> on archetype {} while {}",
+        describe(current_system()),
+        mode(write),
+        short_type_name(type_name),
+        archetype,
+        if holders.is_empty() {
+            String::from("another handle holds it")
+        } else {
+            holders.join(" and ")
+        }
+    );
+}
+
+/// Removes the module paths from a type name. For example, `a::Foo<b::Bar>` becomes `Foo<Bar>`.
+pub(crate) fn short_type_name(name: &str) -> String {
+    let mut short = String::with_capacity(name.len());
+    let mut segment = 0;
+
+    for (i, c) in name.char_indices() {
+        if c.is_alphanumeric() || c == '_' || c == ':' {
+            continue;
+        }
+
+        short.push_str(name[segment..i].rsplit("::").next().unwrap());
+        short.push(c);
+        segment = i + c.len_utf8();
+    }
+    short.push_str(name[segment..].rsplit("::").next().unwrap());
+
+    short
+}
+
+#[cfg(test)]
+mod tests {
+    use super::short_type_name;
+
+    #[test]
+    fn short_type_names() {
+        assert_eq!(short_type_name("Position"), "Position");
+        assert_eq!(short_type_name("game::physics::Velocity"), "Velocity");
+        assert_eq!(
+            short_type_name("std::vec::Vec<(a::B, &c::D<[e::F; 4]>)>"),
+            "Vec<(B, &D<[F; 4]>)>"
+        );
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(
+        expected = "System Integrate requested Write<Velocity> on archetype {Position, Velocity} \
+                    while System Render holds Read"
+    )]
+    fn conflicts_name_systems() {
+        use crate::{component::Component, system::SystemScope, world::World};
+
+        struct Position(#[allow(dead_code)] u32);
+        struct Velocity(#[allow(dead_code)] u32);
+
+        impl Component for Position {}
+        impl Component for Velocity {}
+
+        let mut world = World::new();
+        world.create((vec![Velocity(0)], vec![Position(0)]));
+
+        let buffers = world
+            .archetypes
+            .get_component_buffers::<Velocity>()
+            .unwrap();
+
+        let _reader = {
+            let _scope = SystemScope::enter("game::Render");
+            buffers.get(0)
+        };
+
+        let _scope = SystemScope::enter("game::Integrate");
+        buffers.get_mut(0);
+    }
 }
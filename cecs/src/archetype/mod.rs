@@ -1,89 +1,173 @@
-use std::any::TypeId;
-
-use crate::component::Component;
+use crate::component::{registry::ComponentId, Component};
 
 pub mod access;
 pub mod archetypes;
+pub mod blob;
 pub mod buffer;
+pub mod report;
+pub mod sparse;
 
 /// Describes a set of component types.
+///
+/// Stored as a bitset indexed by component ID, so set operations compare a word of 64 components
+/// at a time. The last word is never zero, which keeps equal sets equal and hashing the same.
 #[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Archetype {
-    ids: Vec<TypeId>,
+    words: Vec<u64>,
 }
 
 impl Archetype {
-    /// Returns an iterator over all of the type IDs of the components within the archetype.
+    /// Returns an iterator over all of the IDs of the components within the archetype, in
+    /// ascending order.
     #[inline]
-    pub fn iter(&self) -> std::slice::Iter<TypeId> {
-        self.ids.iter()
+    pub fn iter(&self) -> impl Iterator<Item = ComponentId> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, word)| {
+            let mut word = *word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros();
+                word &= word - 1;
+                Some(ComponentId::from_index(i * 64 + bit as usize))
+            })
+        })
     }
 
     #[inline]
     pub fn len(&self) -> usize {
-        self.ids.len()
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
     }
 
     #[inline]
     pub fn is_empty(&self) -> bool {
-        self.ids.len() == 0
+        self.words.is_empty()
     }
 
-    pub fn add_component<T: Component + 'static>(&mut self) {
-        let t = TypeId::of::<T>();
-        self.add_component_by_id(t);
+    /// Returns `true` if the archetype contains the given component ID.
+    #[inline]
+    pub fn contains(&self, id: ComponentId) -> bool {
+        let (word, bit) = Self::position(id);
+        self.words
+            .get(word)
+            .is_some_and(|word| word & (1 << bit) != 0)
     }
 
-    pub fn add_component_by_id(&mut self, id: TypeId) {
-        self.ids.push(id);
-        self.ids.sort_unstable();
+    pub fn add_component<T: Component + 'static>(&mut self) {
+        self.add_component_by_id(ComponentId::of::<T>());
     }
 
-    /// Returns `true` if `self` contains any of the same types as `other`.
-    pub fn any_of(&self, other: &Archetype) -> bool {
-        if self.is_empty() || other.is_empty() {
-            return false;
+    /// Does nothing if the archetype already contains the component.
+    pub fn add_component_by_id(&mut self, id: ComponentId) {
+        let (word, bit) = Self::position(id);
+        if self.words.len() <= word {
+            self.words.resize(word + 1, 0);
         }
+        self.words[word] |= 1 << bit;
+    }
 
-        let mut self_i = 0;
-        let mut other_i = 0;
-
-        while self_i != self.ids.len() && other_i != other.ids.len() {
-            let self_id = self.ids[self_i];
-            let other_id = other.ids[other_i];
+    /// Does nothing if the archetype doesn't contain the component.
+    pub fn remove_component_by_id(&mut self, id: ComponentId) {
+        let (word, bit) = Self::position(id);
+        if let Some(word) = self.words.get_mut(word) {
+            *word &= !(1 << bit);
+        }
+        while self.words.last() == Some(&0) {
+            self.words.pop();
+        }
+    }
 
-            if self_id == other_id {
-                return true;
-            } else if self_id < other_id {
-                self_i += 1;
-            } else {
-                other_i += 1;
-            }
+    /// Adds every component of `other` to the archetype.
+    pub fn extend(&mut self, other: &Archetype) {
+        if self.words.len() < other.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
         }
+    }
 
-        false
+    /// Returns `true` if `self` contains any of the same types as `other`.
+    #[inline]
+    pub fn any_of(&self, other: &Archetype) -> bool {
+        self.words.iter().zip(&other.words).any(|(a, b)| a & b != 0)
     }
 
     /// Compares two archetypes and returns `true` if this archetype is a subset of the `other`.
     ///
-    /// That is, every component of this archetype is contained within the `other`. This needs to
-    /// be a fast operation (O(n) fast at least). The speed requirement is probably going to
-    /// dictate the internal representation of the archetype.
+    /// That is, every component of this archetype is contained within the `other`.
+    #[inline]
     pub fn subset_of(&self, other: &Archetype) -> bool {
-        if self.ids.is_empty() {
-            return true;
-        }
+        // Since the last word is never zero, a longer archetype has a component `other` lacks
+        self.words.len() <= other.words.len()
+            && self
+                .words
+                .iter()
+                .zip(&other.words)
+                .all(|(a, b)| a & !b == 0)
+    }
 
-        let mut i = 0;
-        for ty in &other.ids {
-            if *ty == self.ids[i] {
-                i += 1;
-                if i == self.ids.len() {
-                    return true;
-                }
-            }
-        }
+    #[inline]
+    fn position(id: ComponentId) -> (usize, u32) {
+        (id.index() / 64, (id.index() % 64) as u32)
+    }
+}
 
-        false
+#[cfg(test)]
+mod tests {
+    use std::{alloc::Layout, collections::HashSet};
+
+    use super::Archetype;
+    use crate::component::registry::{ComponentId, ComponentRegistry};
+
+    #[test]
+    fn components_across_words() {
+        // Enough components that the archetype spans several words
+        let ids = (0..200)
+            .map(|i| unsafe {
+                ComponentRegistry::register_dynamic(format!("C{}", i), Layout::new::<()>(), None)
+            })
+            .collect::<Vec<ComponentId>>();
+
+        let mut high = Archetype::default();
+        high.add_component_by_id(ids[199]);
+        high.add_component_by_id(ids[3]);
+        high.add_component_by_id(ids[3]);
+        assert_eq!(high.len(), 2);
+        assert_eq!(high.iter().collect::<Vec<_>>(), vec![ids[3], ids[199]]);
+
+        let mut low = Archetype::default();
+        low.add_component_by_id(ids[3]);
+        assert!(low.subset_of(&high));
+        assert!(!high.subset_of(&low));
+        assert!(low.any_of(&high));
+        assert!(!low.contains(ids[199]));
+
+        let mut other = Archetype::default();
+        other.add_component_by_id(ids[4]);
+        assert!(!other.any_of(&high));
+
+        let mut both = other.clone();
+        both.extend(&high);
+        assert_eq!(
+            both.iter().collect::<Vec<_>>(),
+            vec![ids[3], ids[4], ids[199]]
+        );
+
+        // Removing the highest component trims the empty words
+        let mut removed = high.clone();
+        removed.remove_component_by_id(ids[199]);
+        removed.remove_component_by_id(ids[150]);
+        assert_eq!(removed, low);
+
+        // Insertion order doesn't matter for equality or hashing
+        let mut reversed = Archetype::default();
+        reversed.add_component_by_id(ids[3]);
+        reversed.add_component_by_id(ids[199]);
+        assert_eq!(reversed, high);
+        assert_eq!(HashSet::from([high, reversed]).len(), 1);
     }
 }
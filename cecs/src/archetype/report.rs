@@ -0,0 +1,273 @@
+use std::fmt;
+
+use crate::component::registry::{ComponentId, ComponentInfo};
+
+use super::{
+    archetypes::{ArchetypeDescriptorId, Archetypes, MemoryUsage},
+    buffer::short_type_name,
+    Archetype,
+};
+
+/// Describes everything held by the `Archetypes` of a world. Meant for diagnosing problems like
+/// archetype fragmentation, so it is available in release builds too.
+#[derive(Debug, Clone, Default)]
+pub struct StorageReport {
+    /// Every archetype, in descriptor order.
+    pub archetypes: Vec<ArchetypeSummary>,
+    /// Every component with storage, or that is part of an archetype, ordered by component ID.
+    pub components: Vec<ComponentSummary>,
+}
+
+/// Describes a single archetype and the memory used by its data buffers.
+#[derive(Debug, Clone)]
+pub struct ArchetypeSummary {
+    pub id: ArchetypeDescriptorId,
+    pub archetype: Archetype,
+    pub entities: usize,
+    /// Memory used by the entity buffer of the archetype.
+    pub entity_memory: MemoryUsage,
+    /// Memory used by the data buffer of each component, ordered by component ID. Tags have no
+    /// data buffer.
+    pub columns: Vec<(ComponentId, MemoryUsage)>,
+}
+
+/// Describes the storage of a single component type across every archetype.
+#[derive(Debug, Clone)]
+pub struct ComponentSummary {
+    pub info: &'static ComponentInfo,
+    /// Whether the components are kept in a sparse set instead of archetypes.
+    pub sparse: bool,
+    /// Number of archetypes containing the component.
+    pub archetypes: usize,
+    /// Number of entities that have the component.
+    pub entities: usize,
+    pub memory: MemoryUsage,
+}
+
+impl ArchetypeSummary {
+    /// Type names of the components in the archetype, tags included, ordered by component ID.
+    pub fn component_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.archetype.iter().map(|id| id.info().name)
+    }
+
+    /// Memory used by the entities and every component of the archetype.
+    pub fn memory(&self) -> MemoryUsage {
+        self.columns
+            .iter()
+            .fold(self.entity_memory, |total, (_, usage)| total + *usage)
+    }
+}
+
+impl StorageReport {
+    pub(crate) fn new(archetypes: &Archetypes) -> Self {
+        let summaries = archetypes
+            .descriptors()
+            .iter()
+            .enumerate()
+            .map(|(i, descriptor)| {
+                let entity_buffers = archetypes.get_entity_buffers();
+                let mut columns = descriptor
+                    .map
+                    .iter()
+                    .map(|(id, row)| {
+                        let buffers = archetypes.get_buffers(*id).unwrap();
+                        (*id, buffers.memory_usage(*row))
+                    })
+                    .collect::<Vec<_>>();
+                columns.sort_unstable_by_key(|(id, _)| *id);
+
+                ArchetypeSummary {
+                    id: ArchetypeDescriptorId::from(i),
+                    archetype: descriptor.archetype.clone(),
+                    entities: entity_buffers.get(descriptor.entities).len(),
+                    entity_memory: entity_buffers.memory_usage(descriptor.entities),
+                    columns,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Tags have no storage, so they are only found through the archetypes they are part of
+        let mut components = Vec::<ComponentSummary>::default();
+        for (id, memory) in archetypes.memory_by_component() {
+            summary(&mut components, id).memory = memory;
+        }
+        for archetype in &summaries {
+            for id in archetype.archetype.iter() {
+                let summary = summary(&mut components, id);
+                summary.archetypes += 1;
+                summary.entities += archetype.entities;
+            }
+        }
+        for (id, len) in archetypes.sparse_set_lens() {
+            let summary = summary(&mut components, id);
+            summary.sparse = true;
+            summary.entities = len;
+        }
+
+        Self {
+            archetypes: summaries,
+            components,
+        }
+    }
+
+    /// Number of entities across every archetype.
+    pub fn entities(&self) -> usize {
+        self.archetypes
+            .iter()
+            .map(|archetype| archetype.entities)
+            .sum()
+    }
+
+    /// Memory used by every archetype and sparse set.
+    pub fn memory(&self) -> MemoryUsage {
+        let entities = self
+            .archetypes
+            .iter()
+            .fold(MemoryUsage::default(), |total, archetype| {
+                total + archetype.entity_memory
+            });
+        self.components
+            .iter()
+            .fold(entities, |total, component| total + component.memory)
+    }
+}
+
+/// Finds the summary of a component within summaries ordered by component ID, adding it if needed.
+fn summary(components: &mut Vec<ComponentSummary>, id: ComponentId) -> &mut ComponentSummary {
+    let i = match components.binary_search_by_key(&id, |component| component.info.id) {
+        Ok(i) => i,
+        Err(i) => {
+            components.insert(
+                i,
+                ComponentSummary {
+                    info: id.info(),
+                    sparse: false,
+                    archetypes: 0,
+                    entities: 0,
+                    memory: MemoryUsage::default(),
+                },
+            );
+            i
+        }
+    };
+    &mut components[i]
+}
+
+impl fmt::Display for StorageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let memory = self.memory();
+        writeln!(
+            f,
+            "{} entities in {} archetypes, {} of {} bytes used",
+            self.entities(),
+            self.archetypes.len(),
+            memory.used,
+            memory.allocated
+        )?;
+
+        writeln!(f, "Archetypes:")?;
+        for archetype in &self.archetypes {
+            let names = archetype
+                .component_names()
+                .map(short_type_name)
+                .collect::<Vec<_>>();
+            let memory = archetype.memory();
+            writeln!(
+                f,
+                "  #{} {{{}}}: {} entities, {} of {} bytes used",
+                u32::from(archetype.id),
+                names.join(", "),
+                archetype.entities,
+                memory.used,
+                memory.allocated
+            )?;
+            for (id, usage) in &archetype.columns {
+                writeln!(
+                    f,
+                    "    {}: {} of {} bytes used",
+                    short_type_name(id.info().name),
+                    usage.used,
+                    usage.allocated
+                )?;
+            }
+        }
+
+        writeln!(f, "Components:")?;
+        for component in &self.components {
+            writeln!(
+                f,
+                "  {}{}: {} entities in {} archetypes, {} of {} bytes used",
+                short_type_name(component.info.name),
+                if component.sparse { " (sparse)" } else { "" },
+                component.entities,
+                component.archetypes,
+                component.memory.used,
+                component.memory.allocated
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        component::{registry::ComponentId, Component, StorageKind},
+        world::World,
+    };
+
+    struct Position(#[allow(dead_code)] u32);
+    struct Player;
+    struct Selected;
+
+    impl Component for Position {}
+    impl Component for Player {}
+    impl Component for Selected {
+        const STORAGE: StorageKind = StorageKind::SparseSet;
+    }
+
+    #[test]
+    fn report_archetypes_and_components() {
+        let mut world = World::new();
+        world.create((vec![Position(0), Position(1)], vec![Player, Player]));
+        world.create((vec![Position(2)], vec![Selected]));
+
+        let report = world.report();
+        assert_eq!(report.entities(), 3);
+        assert_eq!(report.archetypes.len(), 2);
+
+        let players = &report.archetypes[0];
+        assert_eq!(players.entities, 2);
+        assert_eq!(players.component_names().count(), 2);
+        assert_eq!(players.columns.len(), 1);
+        assert_eq!(players.columns[0].0, ComponentId::of::<Position>());
+        assert_eq!(players.columns[0].1.used, 8);
+
+        let component = |id: ComponentId| {
+            report
+                .components
+                .iter()
+                .find(|component| component.info.id == id)
+                .unwrap()
+        };
+        let position = component(ComponentId::of::<Position>());
+        assert_eq!((position.archetypes, position.entities), (2, 3));
+        assert_eq!(position.memory.used, 12);
+        let player = component(ComponentId::of::<Player>());
+        assert_eq!((player.archetypes, player.entities), (1, 2));
+        assert_eq!(player.memory.allocated, 0);
+        let selected = component(ComponentId::of::<Selected>());
+        assert!(selected.sparse);
+        assert_eq!((selected.archetypes, selected.entities), (0, 1));
+
+        let printed = report.to_string();
+        assert!(printed.starts_with("3 entities in 2 archetypes"));
+        assert!(
+            printed.contains("{Position, Player}: 2 entities")
+                || printed.contains("{Player, Position}: 2 entities")
+        );
+        assert!(printed.contains("Selected (sparse): 1 entities in 0 archetypes"));
+        assert!(format!("{:?}", world).starts_with("World { entities: 3"));
+    }
+}
@@ -0,0 +1,190 @@
+use std::any::Any;
+
+use crate::{entity::Entity, prw_lock::PrwLock};
+
+use super::archetypes::MemoryUsage;
+
+/// Holds the components of a single type that are stored outside of archetypes. Components are
+/// packed together and found through the ID of the entity they belong to, so adding or removing
+/// them doesn't move the rest of the entity's components.
+pub struct SparseSet<T> {
+    /// Index within `dense` of the component of each entity ID, if the entity has one.
+    sparse: Vec<Option<u32>>,
+    dense: Vec<T>,
+    /// Entity each component in `dense` belongs to.
+    entities: Vec<Entity>,
+}
+
+/// A sparse set of any component type. Lets the `Archetypes` manage the memory of every sparse set
+/// without knowing their types.
+pub(crate) trait AnySparseSet: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    fn len(&self) -> usize;
+
+    fn memory_usage(&self) -> MemoryUsage;
+
+    fn shrink_to_fit(&mut self);
+}
+
+impl<T> Default for SparseSet<T> {
+    fn default() -> Self {
+        Self {
+            sparse: Vec::default(),
+            dense: Vec::default(),
+            entities: Vec::default(),
+        }
+    }
+}
+
+impl<T> SparseSet<T> {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.dense.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.dense.is_empty()
+    }
+
+    #[inline]
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.index(entity).is_some()
+    }
+
+    #[inline]
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        self.index(entity).map(|i| &self.dense[i])
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        self.index(entity).map(|i| &mut self.dense[i])
+    }
+
+    /// Adds a component for an entity. If the entity already has one, it is replaced and the old
+    /// component is returned.
+    pub fn insert(&mut self, entity: Entity, component: T) -> Option<T> {
+        if let Some(i) = self.index(entity) {
+            return Some(std::mem::replace(&mut self.dense[i], component));
+        }
+
+        let id = entity.id() as usize;
+        if self.sparse.len() <= id {
+            self.sparse.resize(id + 1, None);
+        }
+        self.sparse[id] = Some(self.dense.len() as u32);
+        self.dense.push(component);
+        self.entities.push(entity);
+        None
+    }
+
+    /// Removes the component of an entity. Returns `None` if the entity doesn't have one.
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        let i = self.index(entity)?;
+        self.sparse[entity.id() as usize] = None;
+
+        // The last component takes the place of the removed one
+        self.entities.swap_remove(i);
+        if let Some(moved) = self.entities.get(i) {
+            self.sparse[moved.id() as usize] = Some(i as u32);
+        }
+        Some(self.dense.swap_remove(i))
+    }
+
+    /// Returns an iterator over every entity that has a component and its component.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.entities.iter().copied().zip(self.dense.iter())
+    }
+
+    /// Memory used and allocated by the set.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage::of(&self.sparse)
+            + MemoryUsage::of(&self.dense)
+            + MemoryUsage::of(&self.entities)
+    }
+
+    /// Frees any memory the set doesn't need to hold its current components.
+    pub fn shrink_to_fit(&mut self) {
+        // Trailing entity IDs without a component don't need a slot
+        while let Some(None) = self.sparse.last() {
+            self.sparse.pop();
+        }
+        self.sparse.shrink_to_fit();
+        self.dense.shrink_to_fit();
+        self.entities.shrink_to_fit();
+    }
+
+    /// Entity IDs are reused, so the version must match too.
+    #[inline]
+    fn index(&self, entity: Entity) -> Option<usize> {
+        let i = (*self.sparse.get(entity.id() as usize)?)? as usize;
+        if self.entities[i] == entity {
+            Some(i)
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> AnySparseSet for PrwLock<SparseSet<T>> {
+    #[inline]
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    #[inline]
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        self.read().memory_usage()
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.write().shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::SparseSet;
+    use crate::entity::Entity;
+
+    fn entity(id: u32, ver: u32) -> Entity {
+        Entity::from_raw_parts(id, NonZeroU32::new(ver).unwrap())
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut set = SparseSet::default();
+        assert_eq!(set.insert(entity(4, 1), 'a'), None);
+        assert_eq!(set.insert(entity(0, 1), 'b'), None);
+        assert_eq!(set.insert(entity(9, 1), 'c'), None);
+        assert_eq!(set.insert(entity(4, 1), 'd'), Some('a'));
+        assert_eq!(set.len(), 3);
+
+        // An old handle with the same ID doesn't see the component
+        assert_eq!(set.get(entity(0, 2)), None);
+
+        assert_eq!(set.remove(entity(4, 1)), Some('d'));
+        assert_eq!(set.remove(entity(4, 1)), None);
+        assert_eq!(set.get(entity(9, 1)), Some(&'c'));
+        assert_eq!(set.get(entity(0, 1)), Some(&'b'));
+        assert_eq!(
+            set.iter().map(|(e, c)| (e.id(), *c)).collect::<Vec<_>>(),
+            vec![(9, 'c'), (0, 'b')]
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use std::{any::TypeId, cell::RefCell};
+
+use crate::{
+    archetype::{
+        archetypes::{ArchetypeDescriptorId, Archetypes},
+        Archetype,
+    },
+    component::{
+        is_tag,
+        pack::ComponentPack,
+        registry::{ComponentId, TypeIdMap},
+        Component, StorageKind,
+    },
+    entity::Entity,
+};
+use paste::*;
+
+pub use cecs_derive::Bundle;
+
+/// The components of a single entity, such as a tuple of components.
+pub trait Bundle: Send + Sync + Sized {
+    /// Vectors the components of many bundles are collected into, so they can be moved into
+    /// storage together.
+    type Pack: ComponentPack;
+
+    /// Creates an archetype which has every component of the bundle stored within archetypes.
+    fn archetype() -> Archetype;
+
+    /// Creates an empty pack with room for the components of `capacity` bundles.
+    ///
+    /// Panics if the bundle contains a component type more than once.
+    fn pack(capacity: usize) -> Self::Pack;
+
+    /// Moves the components onto the end of the vectors of a pack.
+    fn push_into(self, pack: &mut Self::Pack);
+
+    /// Moves the components into component storage along with the entity they belong to.
+    ///
+    /// Returns the ID of the archetype the components are contained within and the index of the
+    /// entity within it (in that order).
+    ///
+    /// Panics if the bundle contains a component type more than once.
+    fn move_into(
+        self,
+        entity: Entity,
+        archetypes: &mut Archetypes,
+    ) -> (ArchetypeDescriptorId, usize);
+}
+
+/// Creates the storage of a component type: its sparse set, or its data buffers if it is stored
+/// in archetypes.
+#[doc(hidden)]
+#[inline]
+pub fn prepare<T: Component + 'static>(archetypes: &mut Archetypes) {
+    if T::STORAGE == StorageKind::SparseSet {
+        archetypes.create_sparse_set::<T>();
+    } else {
+        archetypes.create_component_buffers::<T>();
+    }
+}
+
+/// Adds a component type to an archetype, unless it lives in a sparse set outside of archetypes.
+#[doc(hidden)]
+#[inline]
+pub fn add_to_archetype<T: Component + 'static>(archetype: &mut Archetype) {
+    if T::STORAGE == StorageKind::Table {
+        archetype.add_component::<T>();
+    }
+}
+
+thread_local! {
+    /// Bundle types whose components were already checked to be unique on this thread.
+    static UNIQUE: RefCell<TypeIdMap<()>> = RefCell::default();
+}
+
+/// Panics if the same component ID is given more than once. Only the first call for a bundle type
+/// on every thread gathers the IDs and checks them.
+#[doc(hidden)]
+#[inline]
+pub fn assert_unique<const N: usize>(bundle: TypeId, ids: impl FnOnce() -> [ComponentId; N]) {
+    if UNIQUE.with(|unique| unique.borrow().contains_key(&bundle)) {
+        return;
+    }
+
+    let ids = ids();
+    let mut archetype = Archetype::default();
+    for id in &ids {
+        archetype.add_component_by_id(*id);
+    }
+    assert_eq!(
+        archetype.len(),
+        ids.len(),
+        "Bundle contains a component type more than once"
+    );
+    UNIQUE.with(|unique| unique.borrow_mut().insert(bundle, ()));
+}
+
+/// Pushes entities onto the entity buffer of an archetype, creating the archetype if needed. The
+/// storage of every component must already exist.
+///
+/// Returns the ID of the archetype and the index of the first entity within it (in that order).
+#[doc(hidden)]
+pub fn insert_entities(
+    archetype: Archetype,
+    entities: &[Entity],
+    archetypes: &mut Archetypes,
+) -> (ArchetypeDescriptorId, usize) {
+    let id = archetypes.get_or_create_archetype(archetype);
+    let mut buffer = archetypes
+        .get_entity_buffers()
+        .get_mut(archetypes.descriptor(id).entities);
+    let begin = buffer.len();
+    buffer.extend_from_slice(entities);
+    (id, begin)
+}
+
+/// Moves every component out of a vector and into storage, pairing them with the entities which
+/// were inserted into archetype `id`.
+#[doc(hidden)]
+pub fn append<T: Component + 'static>(
+    components: &mut Vec<T>,
+    entities: &[Entity],
+    id: ArchetypeDescriptorId,
+    archetypes: &Archetypes,
+) {
+    if T::STORAGE == StorageKind::SparseSet {
+        let mut set = archetypes
+            .get_sparse_set::<T>()
+            .expect("Sparse set missing for component in pack.")
+            .write();
+        for (component, entity) in components.drain(..).zip(entities) {
+            set.insert(*entity, component);
+        }
+    } else if is_tag::<T>() {
+        archetypes
+            .get_tag_values::<T>()
+            .expect("Tag storage missing for component in pack.")
+            .write()
+            .append(components);
+    } else {
+        let row = *archetypes
+            .descriptor(id)
+            .map
+            .get(&ComponentId::of::<T>())
+            .expect("Archetype map missing component type in pack.");
+        archetypes
+            .get_component_buffers::<T>()
+            .expect("Component storage missing index.")
+            .get_mut(row)
+            .append(components);
+    }
+}
+
+/// Moves a single component into storage, pairing it with an entity which was inserted into
+/// archetype `id`.
+#[doc(hidden)]
+pub fn push<T: Component + 'static>(
+    component: T,
+    entity: Entity,
+    id: ArchetypeDescriptorId,
+    archetypes: &Archetypes,
+) {
+    if T::STORAGE == StorageKind::SparseSet {
+        archetypes
+            .get_sparse_set::<T>()
+            .expect("Sparse set missing for component in bundle.")
+            .write()
+            .insert(entity, component);
+    } else if is_tag::<T>() {
+        archetypes
+            .get_tag_values::<T>()
+            .expect("Tag storage missing for component in bundle.")
+            .write()
+            .push(component);
+    } else {
+        let row = *archetypes
+            .descriptor(id)
+            .map
+            .get(&ComponentId::of::<T>())
+            .expect("Archetype map missing component type in bundle.");
+        archetypes
+            .get_component_buffers::<T>()
+            .expect("Component storage missing index.")
+            .get_mut(row)
+            .push(component);
+    }
+}
+
+macro_rules! bundle_impl {
+    ( $( $name:ident )+ ) => {
+        /// Implementation for a tuple of components.
+        impl<$($name: Component + 'static, )*> Bundle for ($($name,)*) {
+            type Pack = ($(Vec<$name>,)*);
+
+            #[inline]
+            fn archetype() -> Archetype {
+                let mut archetype = Archetype::default();
+                $(
+                    add_to_archetype::<$name>(&mut archetype);
+                )*
+                archetype
+            }
+
+            #[inline]
+            fn pack(capacity: usize) -> Self::Pack {
+                assert_unique(TypeId::of::<Self>(), || [$(ComponentId::of::<$name>(),)*]);
+                ($(Vec::<$name>::with_capacity(capacity),)*)
+            }
+
+            #[inline]
+            fn push_into(self, pack: &mut Self::Pack) {
+                paste! {
+                    #[allow(non_snake_case)]
+                    let ($([<$name _component>],)*) = self;
+                    #[allow(non_snake_case)]
+                    let ($([<$name _vec>],)*) = pack;
+                    $(
+                        [<$name _vec>].push([<$name _component>]);
+                    )*
+                }
+            }
+
+            fn move_into(
+                self,
+                entity: Entity,
+                archetypes: &mut Archetypes,
+            ) -> (ArchetypeDescriptorId, usize) {
+                assert_unique(TypeId::of::<Self>(), || [$(ComponentId::of::<$name>(),)*]);
+
+                $(
+                    prepare::<$name>(archetypes);
+                )*
+                let (id, index) = insert_entities(Self::archetype(), &[entity], archetypes);
+
+                paste! {
+                    #[allow(non_snake_case)]
+                    let ($([<$name _component>],)*) = self;
+                    $(
+                        push([<$name _component>], entity, id, archetypes);
+                    )*
+                }
+
+                (id, index)
+            }
+        }
+    }
+}
+
+bundle_impl! { A }
+bundle_impl! { A B }
+bundle_impl! { A B C }
+bundle_impl! { A B C D }
+bundle_impl! { A B C D E }
+bundle_impl! { A B C D E F }
+bundle_impl! { A B C D E F G }
+bundle_impl! { A B C D E F G H }
+bundle_impl! { A B C D E F G H I }
+bundle_impl! { A B C D E F G H I J }
+bundle_impl! { A B C D E F G H I J K }
+bundle_impl! { A B C D E F G H I J K L }
+bundle_impl! { A B C D E F G H I J K L M }
+bundle_impl! { A B C D E F G H I J K L M N }
+bundle_impl! { A B C D E F G H I J K L M N O }
+bundle_impl! { A B C D E F G H I J K L M N O P }
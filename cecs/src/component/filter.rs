@@ -1,32 +1,49 @@
 use crate::archetype::{
-    access::{DataBufferAccess, DataBufferSet, ReadDataBuffer, WriteDataBuffer},
+    access::{DataBufferAccess, DataBufferSet, PresenceBuffer, ReadDataBuffer, WriteDataBuffer},
     archetypes::{ArchetypeDescriptor, Archetypes},
     Archetype,
 };
 
-use std::any::TypeId;
+use paste::*;
 
-use super::Component;
+use super::{is_tag, registry::ComponentId, Component, StorageKind};
 
 /// Describes a particular way to access a subset of entities based on what components they have.
 pub trait ComponentFilter {
     type StorageSet: DataBufferSet;
 
-    /// Creates an archetype which has every component within the filter.
+    /// Creates an archetype which has every component matching entities must have.
     fn archetype() -> Archetype;
 
-    /// Creates an archetype which contains only components that are read.
+    /// Creates an archetype which contains only components that are read. Components whose data
+    /// isn't accessed, like tags, are left out.
     fn read_archetype() -> Archetype;
 
-    /// Creates an archetype which contains only components that are written.
+    /// Creates an archetype which contains only components that are written. Components whose
+    /// data isn't accessed, like tags, are left out.
     fn write_archetype() -> Archetype;
 
-    /// Given an archetype descriptor, generates an instance of the storage set for the filter.
+    /// Creates an archetype which contains only components stored within archetypes. Matching
+    /// archetypes must be a superset of it.
+    fn table_archetype() -> Archetype;
+
+    /// Creates an archetype which contains the components matching entities must not have.
+    /// Matching archetypes must not have any of them.
+    fn excluded_archetype() -> Archetype;
+
+    /// Given an archetype descriptor, pushes the index of the data buffer of each component in the
+    /// filter (in filter order) onto `columns`. Components without a data buffer, because they
+    /// have sparse set storage or their data isn't accessed, push `usize::MAX`.
     ///
-    /// Panics if the filter isn't a subset of the descriptor.
+    /// Panics if the table archetype of the filter isn't a subset of the descriptor.
+    fn columns(descriptor: &ArchetypeDescriptor, columns: &mut Vec<usize>);
+
+    /// Given the data buffer indices produced by `columns`, generates an instance of the storage
+    /// set for the filter. Sparse set storage is shared with `previous` if provided.
     fn make_storage_set(
-        descriptor: &ArchetypeDescriptor,
+        columns: &[usize],
         archetypes: &Archetypes,
+        previous: Option<&Self::StorageSet>,
     ) -> Self::StorageSet;
 }
 
@@ -40,6 +57,13 @@ pub trait ComponentAccess {
 
     /// Indicates this access type needs mutable access.
     const MUTABLE: bool;
+
+    /// Indicates the component's data is accessed, which takes a lock on its storage and
+    /// conflicts with systems that write to it. Tags have no data to access.
+    const ACCESSED: bool = !is_tag::<Self::Component>();
+
+    /// Indicates matching entities must not have the component.
+    const EXCLUDED: bool = false;
 }
 
 pub struct Read<T: Component> {
@@ -50,30 +74,75 @@ pub struct Write<T: Component> {
     _phantom: std::marker::PhantomData<T>,
 }
 
-impl<'a, C: Component + 'static> ComponentAccess for &'a C {
+/// Matches entities that have a component, without accessing it. Only supports components stored
+/// within archetypes.
+pub struct With<T: Component> {
+    _phantom: std::marker::PhantomData<T>,
+}
+
+/// Matches entities that don't have a component. Only supports components stored within
+/// archetypes.
+pub struct Without<T: Component> {
+    _phantom: std::marker::PhantomData<T>,
+}
+
+impl<C: Component + 'static> ComponentAccess for &C {
     type Component = C;
     type Storage = ReadDataBuffer<C>;
     const MUTABLE: bool = false;
 }
 
-impl<'a, C: Component + 'static> ComponentAccess for &'a mut C {
+impl<C: Component + 'static> ComponentAccess for &mut C {
     type Component = C;
     type Storage = WriteDataBuffer<C>;
     const MUTABLE: bool = true;
 }
 
-impl<'a, C: Component + 'static> ComponentAccess for Read<C> {
+impl<C: Component + 'static> ComponentAccess for Read<C> {
     type Component = C;
     type Storage = ReadDataBuffer<C>;
     const MUTABLE: bool = false;
 }
 
-impl<'a, C: Component + 'static> ComponentAccess for Write<C> {
+impl<C: Component + 'static> ComponentAccess for Write<C> {
     type Component = C;
     type Storage = WriteDataBuffer<C>;
     const MUTABLE: bool = true;
 }
 
+impl<C: Component + 'static> ComponentAccess for With<C> {
+    type Component = C;
+    type Storage = PresenceBuffer<Self>;
+    const MUTABLE: bool = false;
+    const ACCESSED: bool = false;
+}
+
+impl<C: Component + 'static> ComponentAccess for Without<C> {
+    type Component = C;
+    type Storage = PresenceBuffer<Self>;
+    const MUTABLE: bool = false;
+    const ACCESSED: bool = false;
+    const EXCLUDED: bool = true;
+}
+
+impl<T: Component> Default for With<T> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T: Component> Default for Without<T> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
 macro_rules! component_filter_impl {
     ( $n:expr, $( $name:ident )+ ) => {
         impl<$($name: ComponentAccess,)*> ComponentFilter for ($($name,)*) {
@@ -83,7 +152,9 @@ macro_rules! component_filter_impl {
             fn archetype() -> Archetype {
                 let mut archetype = Archetype::default();
                 $(
-                    archetype.add_component::<$name::Component>();
+                    if !$name::EXCLUDED {
+                        archetype.add_component::<$name::Component>();
+                    }
                 )*
                 archetype
             }
@@ -92,7 +163,7 @@ macro_rules! component_filter_impl {
             fn read_archetype() -> Archetype {
                 let mut archetype = Archetype::default();
                 $(
-                    if !$name::MUTABLE {
+                    if $name::ACCESSED && !$name::MUTABLE {
                         archetype.add_component::<$name::Component>();
                     }
                 )*
@@ -103,7 +174,18 @@ macro_rules! component_filter_impl {
             fn write_archetype() -> Archetype {
                 let mut archetype = Archetype::default();
                 $(
-                    if $name::MUTABLE {
+                    if $name::ACCESSED && $name::MUTABLE {
+                        archetype.add_component::<$name::Component>();
+                    }
+                )*
+                archetype
+            }
+
+            #[inline]
+            fn table_archetype() -> Archetype {
+                let mut archetype = Archetype::default();
+                $(
+                    if $name::Component::STORAGE == StorageKind::Table && !$name::EXCLUDED {
                         archetype.add_component::<$name::Component>();
                     }
                 )*
@@ -111,14 +193,48 @@ macro_rules! component_filter_impl {
             }
 
             #[inline]
-            fn make_storage_set(descriptor: &ArchetypeDescriptor, archetypes: &Archetypes)
-                -> Self::StorageSet {
-                ($(
-                    $name::Storage::new(
-                        archetypes,
-                        *descriptor.map.get(&TypeId::of::<$name::Component>()
-                    ).expect("Provided archetype does not contain component in filter.")),
-                )*)
+            fn excluded_archetype() -> Archetype {
+                let mut archetype = Archetype::default();
+                $(
+                    if $name::EXCLUDED {
+                        archetype.add_component::<$name::Component>();
+                    }
+                )*
+                archetype
+            }
+
+            #[inline]
+            fn columns(descriptor: &ArchetypeDescriptor, columns: &mut Vec<usize>) {
+                $(
+                    columns.push(if $name::ACCESSED && $name::Component::STORAGE == StorageKind::Table {
+                        *descriptor
+                            .map
+                            .get(&ComponentId::of::<$name::Component>())
+                            .expect("Provided archetype does not contain component in filter.")
+                    } else {
+                        usize::MAX
+                    });
+                )*
+            }
+
+            #[inline]
+            fn make_storage_set(
+                columns: &[usize],
+                archetypes: &Archetypes,
+                previous: Option<&Self::StorageSet>,
+            ) -> Self::StorageSet {
+                paste! {
+                    #[allow(non_snake_case)]
+                    let ($([<$name _previous>],)*) = match previous {
+                        Some(($([<$name _previous>],)*)) => ($(Some([<$name _previous>]),)*),
+                        None => ($(None::<&$name::Storage>,)*),
+                    };
+                }
+
+                let mut columns = columns.iter();
+                paste! { ($(
+                    $name::Storage::new(archetypes, *columns.next().unwrap(), [<$name _previous>]),
+                )*) }
             }
         }
     }
@@ -151,9 +267,9 @@ mod tests {
         },
     };
 
-    struct A {}
-    struct B {}
-    struct C {}
+    struct A(#[allow(dead_code)] u32);
+    struct B(#[allow(dead_code)] u32);
+    struct C(#[allow(dead_code)] u32);
 
     impl Component for A {}
     impl Component for B {}
@@ -169,7 +285,7 @@ mod tests {
             }
 
             for (a, b) in a.iter().zip(b.iter()) {
-                if *a != *b {
+                if a != b {
                     return false;
                 }
             }
@@ -1,5 +1,39 @@
+pub mod bundle;
 pub mod filter;
 pub mod pack;
+pub mod registry;
+
+pub use cecs_derive::Component;
 
 /// A component holds a type of data associated with an entity.
-pub trait Component: Send + Sync {}
+pub trait Component: Send + Sync {
+    /// Where components of this type are kept.
+    const STORAGE: StorageKind = StorageKind::Table;
+
+    /// Name the component is registered under, which shows up in access conflicts and storage
+    /// reports. Defaults to the type name.
+    const NAME: Option<&'static str> = None;
+}
+
+/// Describes how the components of a type are stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StorageKind {
+    /// Components are stored alongside the other components of their archetype. Fastest to
+    /// iterate over, but adding or removing the component moves every other component of the
+    /// entity to a different archetype.
+    Table,
+    /// Components are stored in a sparse set keyed by entity ID, outside of any archetype. Meant
+    /// for components that are added and removed often.
+    SparseSet,
+}
+
+/// Whether components of a type are tags: zero sized components without drop glue stored in
+/// archetypes. Tags have no data to store, so they only make up the identity of the archetypes
+/// they are part of. They have no data buffers, accessing them takes no lock, and systems don't
+/// conflict over them. Matches `ComponentInfo::is_tag` of the component.
+#[inline(always)]
+pub const fn is_tag<T: Component>() -> bool {
+    std::mem::size_of::<T>() == 0
+        && !std::mem::needs_drop::<T>()
+        && matches!(T::STORAGE, StorageKind::Table)
+}
@@ -0,0 +1,357 @@
+use std::{
+    alloc::Layout,
+    any::TypeId,
+    cell::RefCell,
+    collections::HashMap,
+    hash::{BuildHasherDefault, Hasher},
+    sync::{OnceLock, RwLock},
+};
+
+use super::{Component, StorageKind};
+
+/// Dense numeric ID of a component type. IDs are handed out in the order types are registered,
+/// starting at zero, and are shared by every world in the process.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ComponentId(u32);
+
+/// Everything the registry knows about a component type.
+#[derive(Debug)]
+pub struct ComponentInfo {
+    pub id: ComponentId,
+    /// Type name of the component, or the name it was registered with if it is dynamic.
+    pub name: &'static str,
+    pub layout: Layout,
+    /// Where the components are kept. Dynamic components are always stored in archetypes.
+    pub storage: StorageKind,
+    /// Drops a component in place. `None` if the component doesn't need to be dropped.
+    pub drop: Option<unsafe fn(*mut u8)>,
+    /// `None` if the component is dynamic.
+    pub type_id: Option<TypeId>,
+}
+
+/// Assigns IDs to component types and keeps track of how to handle their data without knowing
+/// their type. There is a single registry per process.
+#[derive(Default)]
+pub struct ComponentRegistry {
+    /// Indexed by component ID. Infos are leaked so references to them can be handed out freely.
+    components: Vec<&'static ComponentInfo>,
+    by_type: TypeIdMap<ComponentId>,
+}
+
+/// Map keyed by type ID. Type IDs are already hashes, so they are used as is.
+pub(crate) type TypeIdMap<V> = HashMap<TypeId, V, BuildHasherDefault<TypeIdHasher>>;
+
+/// Hasher that passes the hash a type ID is made of through.
+#[derive(Default)]
+pub(crate) struct TypeIdHasher(u64);
+
+impl Hasher for TypeIdHasher {
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = self.0.rotate_left(8) ^ u64::from(*byte);
+        }
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.0 ^= i;
+    }
+}
+
+/// Copy of the parts of the registry a thread has looked up. The registry only ever grows, so
+/// the copy never goes stale and lookups after the first don't need to take the lock.
+#[derive(Default)]
+struct LocalRegistry {
+    components: Vec<&'static ComponentInfo>,
+    by_type: TypeIdMap<ComponentId>,
+}
+
+static REGISTRY: OnceLock<RwLock<ComponentRegistry>> = OnceLock::new();
+
+thread_local! {
+    static LOCAL: RefCell<LocalRegistry> = RefCell::default();
+}
+
+impl ComponentId {
+    /// Gets the ID of a component type, registering it under the component's name if it hasn't
+    /// been registered yet.
+    #[inline]
+    pub fn of<T: Component + 'static>() -> Self {
+        ComponentRegistry::register::<T>(T::NAME.unwrap_or(std::any::type_name::<T>()), T::STORAGE)
+    }
+
+    /// Index of the ID, which is lower than the number of registered components.
+    #[inline]
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    /// Inverse of [`ComponentId::index`].
+    #[inline]
+    pub(crate) fn from_index(index: usize) -> Self {
+        Self(index as u32)
+    }
+
+    #[inline]
+    pub fn info(self) -> &'static ComponentInfo {
+        ComponentRegistry::info(self)
+    }
+}
+
+impl ComponentInfo {
+    /// Whether the component is a tag: a zero sized component without drop glue stored in
+    /// archetypes, which has no data buffers. Matches `component::is_tag` for the type of the
+    /// component.
+    #[inline]
+    pub fn is_tag(&self) -> bool {
+        self.layout.size() == 0 && self.drop.is_none() && self.storage == StorageKind::Table
+    }
+}
+
+impl ComponentRegistry {
+    #[inline]
+    fn global() -> &'static RwLock<ComponentRegistry> {
+        REGISTRY.get_or_init(RwLock::default)
+    }
+
+    /// Gets the ID of a type that isn't a component, such as an event channel, registering it
+    /// under its type name if it hasn't been registered yet. Components are registered through
+    /// `ComponentId::of`, so they always get the name they declare.
+    #[inline]
+    pub(crate) fn id<T: Send + Sync + 'static>() -> ComponentId {
+        Self::register::<T>(std::any::type_name::<T>(), StorageKind::Table)
+    }
+
+    /// Gets the ID of a type, registering it under `name` with the given storage if it hasn't been
+    /// registered yet.
+    #[inline]
+    fn register<T: Send + Sync + 'static>(name: &'static str, storage: StorageKind) -> ComponentId {
+        let type_id = TypeId::of::<T>();
+        if let Some(id) = LOCAL.with(|local| local.borrow().by_type.get(&type_id).copied()) {
+            return id;
+        }
+
+        let id = Self::register_global::<T>(name, storage);
+        LOCAL.with(|local| local.borrow_mut().by_type.insert(type_id, id));
+        id
+    }
+
+    fn register_global<T: Send + Sync + 'static>(
+        name: &'static str,
+        storage: StorageKind,
+    ) -> ComponentId {
+        let type_id = TypeId::of::<T>();
+        if let Some(id) = Self::global().read().unwrap().by_type.get(&type_id) {
+            return *id;
+        }
+
+        let mut registry = Self::global().write().unwrap();
+
+        // Another thread might have registered the type while we waited for the lock
+        if let Some(id) = registry.by_type.get(&type_id) {
+            return *id;
+        }
+
+        let drop = if std::mem::needs_drop::<T>() {
+            Some(drop_ptr::<T> as unsafe fn(*mut u8))
+        } else {
+            None
+        };
+        let id = registry.push(name, Layout::new::<T>(), storage, drop, Some(type_id));
+        registry.by_type.insert(type_id, id);
+        id
+    }
+
+    /// Gets the ID of a type. Returns `None` if the type hasn't been registered.
+    pub fn get<T: 'static>() -> Option<ComponentId> {
+        let type_id = TypeId::of::<T>();
+        if let Some(id) = LOCAL.with(|local| local.borrow().by_type.get(&type_id).copied()) {
+            return Some(id);
+        }
+
+        Self::global()
+            .read()
+            .unwrap()
+            .by_type
+            .get(&type_id)
+            .copied()
+    }
+
+    /// Registers a component that has no Rust type. Every call registers a new component, even if
+    /// the name was used before.
+    ///
+    /// # Safety
+    /// If provided, `drop` must be safe to call on a pointer to data with the given layout that
+    /// was initialized for this component. The data of the component must be safe to send to and
+    /// share between threads, like the data of `Send + Sync` types.
+    pub unsafe fn register_dynamic(
+        name: impl Into<String>,
+        layout: Layout,
+        drop: Option<unsafe fn(*mut u8)>,
+    ) -> ComponentId {
+        let name = Box::leak(name.into().into_boxed_str());
+        Self::global()
+            .write()
+            .unwrap()
+            .push(name, layout, StorageKind::Table, drop, None)
+    }
+
+    /// Panics if the ID wasn't handed out by the registry.
+    #[inline]
+    pub fn info(id: ComponentId) -> &'static ComponentInfo {
+        LOCAL.with(|local| {
+            if let Some(info) = local.borrow().components.get(id.index()) {
+                return *info;
+            }
+
+            // Catch up with everything registered since the last miss
+            let mut local = local.borrow_mut();
+            let known = local.components.len();
+            local
+                .components
+                .extend_from_slice(&Self::global().read().unwrap().components[known..]);
+            local.components[id.index()]
+        })
+    }
+
+    /// Number of registered components.
+    #[inline]
+    pub fn len() -> usize {
+        Self::global().read().unwrap().components.len()
+    }
+
+    fn push(
+        &mut self,
+        name: &'static str,
+        layout: Layout,
+        storage: StorageKind,
+        drop: Option<unsafe fn(*mut u8)>,
+        type_id: Option<TypeId>,
+    ) -> ComponentId {
+        let id = ComponentId(
+            u32::try_from(self.components.len()).expect("Too many registered components"),
+        );
+        self.components.push(Box::leak(Box::new(ComponentInfo {
+            id,
+            name,
+            layout,
+            storage,
+            drop,
+            type_id,
+        })));
+        id
+    }
+}
+
+/// Helper function that drops a value of a known type through an untyped pointer.
+unsafe fn drop_ptr<T>(ptr: *mut u8) {
+    ptr.cast::<T>().drop_in_place();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ComponentId, ComponentRegistry};
+    use crate::component::{is_tag, Component, StorageKind};
+    use std::{alloc::Layout, sync::Arc};
+
+    #[test]
+    fn register_components() {
+        struct Position(#[allow(dead_code)] f32, #[allow(dead_code)] f32);
+        impl Component for Position {}
+
+        let position = ComponentId::of::<Position>();
+        assert_eq!(ComponentId::of::<Position>(), position);
+        assert_eq!(ComponentRegistry::get::<Position>(), Some(position));
+        assert!(position.index() < ComponentRegistry::len());
+
+        let info = position.info();
+        assert_eq!(info.id, position);
+        assert!(info.name.ends_with("Position"));
+        assert_eq!(info.layout, Layout::new::<(f32, f32)>());
+        assert!(info.drop.is_none());
+
+        struct Unregistered;
+        assert_eq!(ComponentRegistry::get::<Unregistered>(), None);
+
+        let dynamic =
+            unsafe { ComponentRegistry::register_dynamic("Health", Layout::new::<u32>(), None) };
+        assert_ne!(dynamic, position);
+        assert_eq!(dynamic.info().name, "Health");
+        assert_eq!(dynamic.info().type_id, None);
+    }
+
+    #[test]
+    fn components_keep_their_name() {
+        struct Velocity;
+        impl Component for Velocity {
+            const NAME: Option<&'static str> = Some("Speed");
+        }
+
+        // Looking the type up doesn't register it under its type name first
+        assert_eq!(ComponentRegistry::get::<Velocity>(), None);
+        assert_eq!(ComponentId::of::<Velocity>().info().name, "Speed");
+    }
+
+    #[test]
+    fn sparse_zero_sized_components_are_not_tags() {
+        struct Tag;
+        struct Marker;
+        impl Component for Tag {}
+        impl Component for Marker {
+            const STORAGE: StorageKind = StorageKind::SparseSet;
+        }
+
+        assert!(ComponentId::of::<Tag>().info().is_tag());
+        let marker = ComponentId::of::<Marker>().info();
+        assert_eq!(marker.storage, StorageKind::SparseSet);
+        assert!(!marker.is_tag());
+    }
+
+    #[test]
+    fn zero_sized_components_with_drop_glue_are_not_tags() {
+        struct Guard;
+        impl Component for Guard {}
+        impl Drop for Guard {
+            fn drop(&mut self) {}
+        }
+
+        assert!(!is_tag::<Guard>());
+        assert!(!ComponentId::of::<Guard>().info().is_tag());
+    }
+
+    #[test]
+    fn lookups_across_threads() {
+        struct Local;
+        struct Remote;
+        impl Component for Local {}
+        impl Component for Remote {}
+
+        let local = ComponentId::of::<Local>();
+        assert_eq!(local.info().id, local);
+        let id = std::thread::spawn(ComponentId::of::<Remote>)
+            .join()
+            .unwrap();
+
+        // This thread already cached some infos, and has to catch up with the new one
+        assert_eq!(id.info().id, id);
+        assert_eq!(ComponentRegistry::get::<Remote>(), Some(id));
+        assert_eq!(ComponentId::of::<Remote>(), id);
+    }
+
+    #[test]
+    fn drop_through_registry() {
+        let shared = Arc::new(());
+        let mut value = std::mem::ManuallyDrop::new(shared.clone());
+        assert_eq!(Arc::strong_count(&shared), 2);
+
+        let drop = ComponentRegistry::id::<Arc<()>>().info().drop.unwrap();
+        unsafe { drop(&mut *value as *mut Arc<()> as *mut u8) };
+        assert_eq!(Arc::strong_count(&shared), 1);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::{event::Event, world::World};
+
+/// A predicate that decides whether a system runs during a particular run of its stage.
+///
+/// Conditions are evaluated every time the stage of the system runs, before any system of the
+/// stage starts, so they may look at the world without conflicting with running systems. That is
+/// once per run of the dispatcher, except for the fixed update stage, which runs once per fixed
+/// step.
+pub trait RunCondition {
+    fn should_run(&mut self, world: &World) -> bool;
+}
+
+impl<F: FnMut(&World) -> bool> RunCondition for F {
+    #[inline]
+    fn should_run(&mut self, world: &World) -> bool {
+        self(world)
+    }
+}
+
+/// Passes once every `n` runs of the stage of the system, starting with the first run. In the
+/// fixed update stage, that is once every `n` fixed steps.
+pub fn every_nth(n: usize) -> impl RunCondition {
+    assert_ne!(n, 0);
+
+    let mut count = 0;
+    move |_: &World| {
+        let should_run = count == 0;
+        count = (count + 1) % n;
+        should_run
+    }
+}
+
+/// Passes if there are any buffered events of type `E`.
+///
+/// Panics if the event type was never registered.
+pub fn on_event<E: Event + 'static>() -> impl RunCondition {
+    |world: &World| {
+        !world
+            .events
+            .get::<E>()
+            .expect("Condition on unregistered event type")
+            .read()
+            .is_empty()
+    }
+}
+
+/// Passes if the provided condition fails.
+pub fn not(mut condition: impl RunCondition) -> impl RunCondition {
+    move |world: &World| !condition.should_run(world)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{every_nth, not, on_event};
+    use crate::{
+        dispatcher::{Dispatcher, Stage},
+        event::Event,
+        system::{query::QueryGenerator, System},
+        world::World,
+    };
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    struct Counter(Arc<AtomicUsize>);
+
+    impl System for Counter {
+        type Components = ();
+
+        fn tick(&mut self, _: QueryGenerator) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    struct Ping;
+
+    impl Event for Ping {}
+
+    #[test]
+    fn skipped_systems_release_dependents() {
+        let mut world = World::new();
+        world.add_events::<Ping>();
+
+        let paused = Arc::new(AtomicBool::new(false));
+        let nth = Arc::new(AtomicUsize::new(0));
+        let unpaused = Arc::new(AtomicUsize::new(0));
+        let pinged = Arc::new(AtomicUsize::new(0));
+        let dependent = Arc::new(AtomicUsize::new(0));
+
+        let mut builder = Dispatcher::builder();
+        let a = builder.with_system(Counter(nth.clone()), &[]);
+        builder.run_if(a, every_nth(3));
+
+        let b = builder.with_system(Counter(unpaused.clone()), &[a]);
+        let is_paused = paused.clone();
+        builder.run_if(b, not(move |_: &World| is_paused.load(Ordering::Relaxed)));
+
+        let c = builder.with_system(Counter(pinged.clone()), &[b]);
+        builder.run_if(c, on_event::<Ping>());
+
+        // Depends on a chain of systems that are all skipped at some point
+        builder.with_system(Counter(dependent.clone()), &[c]);
+
+        let mut dispatcher = builder.build();
+
+        for i in 0..6 {
+            paused.store(i >= 4, Ordering::Relaxed);
+            if i == 1 {
+                world.send_event(Ping);
+            }
+
+            dispatcher.run(&mut world);
+        }
+
+        assert_eq!(nth.load(Ordering::Relaxed), 2);
+        assert_eq!(unpaused.load(Ordering::Relaxed), 4);
+        assert_eq!(pinged.load(Ordering::Relaxed), 1);
+        assert_eq!(dependent.load(Ordering::Relaxed), 6);
+    }
+
+    #[test]
+    fn fixed_update_conditions_run_every_step() {
+        let mut world = World::new();
+        let every = Arc::new(AtomicUsize::new(0));
+        let nth = Arc::new(AtomicUsize::new(0));
+
+        let mut builder = Dispatcher::builder().fixed_step(Duration::from_millis(10));
+        builder.with_system_in_stage(Stage::FixedUpdate, Counter(every.clone()), &[]);
+        let system = builder.with_system_in_stage(Stage::FixedUpdate, Counter(nth.clone()), &[]);
+        builder.run_if(system, every_nth(2));
+        let mut dispatcher = builder.build();
+
+        // A single run with five fixed steps evaluates the condition five times
+        dispatcher.run_with_delta(&mut world, Duration::from_millis(50));
+        assert_eq!(every.load(Ordering::Relaxed), 5);
+        assert_eq!(nth.load(Ordering::Relaxed), 3);
+    }
+}
@@ -0,0 +1,324 @@
+//! Runs randomly generated system graphs through the dispatcher and checks that conflicting
+//! systems never overlap and that dependencies always finish first.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    archetype::Archetype,
+    component::registry::{ComponentId, ComponentRegistry},
+    rng::Rng,
+    system::{query::QueryGenerator, System},
+    world::World,
+};
+
+use super::{Dispatcher, SystemId};
+
+/// Number of component types systems pick from. Kept small so conflicts are common.
+const COMPONENTS: usize = 6;
+
+struct Slot<const N: usize>;
+
+fn component_ids() -> [ComponentId; COMPONENTS] {
+    [
+        ComponentRegistry::id::<Slot<0>>(),
+        ComponentRegistry::id::<Slot<1>>(),
+        ComponentRegistry::id::<Slot<2>>(),
+        ComponentRegistry::id::<Slot<3>>(),
+        ComponentRegistry::id::<Slot<4>>(),
+        ComponentRegistry::id::<Slot<5>>(),
+    ]
+}
+
+/// A randomly generated system.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SystemDesc {
+    /// Indices of the components the system reads.
+    pub read: Vec<usize>,
+    /// Indices of the components the system writes.
+    pub write: Vec<usize>,
+    /// Indices of systems that must finish first. Always lower than the index of the system, so
+    /// the graph is acyclic.
+    pub dependencies: Vec<usize>,
+    pub enabled: bool,
+    pub work: Duration,
+}
+
+impl SystemDesc {
+    /// Two systems conflict if one of them writes to a component the other one accesses.
+    pub fn conflicts_with(&self, other: &SystemDesc) -> bool {
+        let accesses = |system: &SystemDesc, component: &usize| {
+            system.read.contains(component) || system.write.contains(component)
+        };
+
+        self.write.iter().any(|c| accesses(other, c))
+            || other.write.iter().any(|c| accesses(self, c))
+    }
+}
+
+/// Generates a random set of systems with random access sets and random acyclic dependencies.
+pub(crate) fn random_graph(rng: &mut Rng, max_systems: usize) -> Vec<SystemDesc> {
+    let count = 1 + rng.below(max_systems);
+    let mut systems = Vec::with_capacity(count);
+
+    for i in 0..count {
+        let mut system = SystemDesc {
+            enabled: !rng.chance(10),
+            // A few slow systems make it likely that others finish while they are still running
+            work: if rng.chance(15) {
+                Duration::from_micros(500 + rng.below(1500) as u64)
+            } else {
+                Duration::from_micros(rng.below(50) as u64)
+            },
+            ..SystemDesc::default()
+        };
+
+        for component in 0..COMPONENTS {
+            if rng.chance(15) {
+                system.write.push(component);
+            } else if rng.chance(25) {
+                system.read.push(component);
+            }
+        }
+
+        for dependency in 0..i {
+            if rng.chance(10) {
+                system.dependencies.push(dependency);
+            }
+        }
+
+        systems.push(system);
+    }
+
+    systems
+}
+
+/// What the systems of a graph observed while running.
+#[derive(Default)]
+struct Observations {
+    running: Vec<usize>,
+    finished: Vec<bool>,
+    runs: Vec<usize>,
+    violations: Vec<String>,
+}
+
+struct FuzzSystem {
+    idx: usize,
+    graph: Arc<Vec<SystemDesc>>,
+    /// Every system each system depends on, directly or through other systems.
+    ancestors: Arc<Vec<Vec<usize>>>,
+    observations: Arc<Mutex<Observations>>,
+}
+
+impl System for FuzzSystem {
+    type Components = ();
+
+    fn tick(&mut self, _: QueryGenerator) {
+        let desc = &self.graph[self.idx];
+
+        {
+            let mut observations = self.observations.lock().unwrap();
+            let observations = &mut *observations;
+
+            for other in &observations.running {
+                if desc.conflicts_with(&self.graph[*other]) {
+                    observations.violations.push(format!(
+                        "System {} started while conflicting system {} was running",
+                        self.idx, other
+                    ));
+                }
+            }
+
+            // Disabled systems still order the systems around them
+            for ancestor in &self.ancestors[self.idx] {
+                if !observations.finished[*ancestor] && self.graph[*ancestor].enabled {
+                    observations.violations.push(format!(
+                        "System {} started before its ancestor {} finished",
+                        self.idx, ancestor
+                    ));
+                }
+            }
+
+            observations.running.push(self.idx);
+        }
+
+        let start = Instant::now();
+        while start.elapsed() < desc.work {
+            std::hint::spin_loop();
+        }
+
+        let mut observations = self.observations.lock().unwrap();
+        observations.running.retain(|idx| *idx != self.idx);
+        observations.finished[self.idx] = true;
+        observations.runs[self.idx] += 1;
+    }
+}
+
+/// Finds every system each system depends on, directly or through other systems.
+fn ancestors(graph: &[SystemDesc]) -> Vec<Vec<usize>> {
+    let mut ancestors = Vec::<Vec<usize>>::with_capacity(graph.len());
+    for desc in graph {
+        // Dependencies always come first, so their ancestors are already known
+        let mut found = vec![false; ancestors.len()];
+        for dependency in &desc.dependencies {
+            found[*dependency] = true;
+            for ancestor in &ancestors[*dependency] {
+                found[*ancestor] = true;
+            }
+        }
+        ancestors.push((0..found.len()).filter(|idx| found[*idx]).collect());
+    }
+    ancestors
+}
+
+/// Builds a dispatcher for the graph, runs it a few times and returns every violation that was
+/// observed.
+fn check_graph(graph: Vec<SystemDesc>, critical_path: bool) -> Vec<String> {
+    const RUNS: usize = 3;
+
+    let components = component_ids();
+    let ancestors = Arc::new(ancestors(&graph));
+    let graph = Arc::new(graph);
+    let observations = Arc::new(Mutex::new(Observations {
+        finished: vec![false; graph.len()],
+        runs: vec![0; graph.len()],
+        ..Observations::default()
+    }));
+
+    let mut builder = Dispatcher::builder()
+        .thread_count(4)
+        .critical_path(critical_path);
+    let mut ids = Vec::<SystemId>::with_capacity(graph.len());
+
+    for (idx, desc) in graph.iter().enumerate() {
+        let archetype = |indices: &[usize]| {
+            let mut archetype = Archetype::default();
+            for component in indices {
+                archetype.add_component_by_id(components[*component]);
+            }
+            archetype
+        };
+        let dependencies = desc
+            .dependencies
+            .iter()
+            .map(|dependency| ids[*dependency])
+            .collect::<Vec<_>>();
+
+        let system = FuzzSystem {
+            idx,
+            graph: graph.clone(),
+            ancestors: ancestors.clone(),
+            observations: observations.clone(),
+        };
+        ids.push(builder.with_system_accessing(
+            system,
+            &archetype(&desc.read),
+            &archetype(&desc.write),
+            &dependencies,
+        ));
+    }
+
+    let mut dispatcher = builder.build();
+    for (id, desc) in ids.iter().zip(graph.iter()) {
+        dispatcher.set_enabled(*id, desc.enabled);
+    }
+
+    let mut world = World::new();
+    for _ in 0..RUNS {
+        dispatcher.run(&mut world);
+
+        let mut observations = observations.lock().unwrap();
+        assert!(observations.running.is_empty());
+        observations
+            .finished
+            .iter_mut()
+            .for_each(|finished| *finished = false);
+    }
+
+    let mut observations = observations.lock().unwrap();
+    for (idx, desc) in graph.iter().enumerate() {
+        let expected = if desc.enabled { RUNS } else { 0 };
+        let runs = observations.runs[idx];
+        if runs != expected {
+            observations.violations.push(format!(
+                "System {} ran {} times instead of {}",
+                idx, runs, expected
+            ));
+        }
+    }
+
+    std::mem::take(&mut observations.violations)
+}
+
+#[test]
+fn random_graphs_respect_conflicts_and_dependencies() {
+    for seed in 0..60 {
+        let graph = random_graph(&mut Rng::new(seed), 24);
+        let critical_path = seed % 2 == 1;
+
+        let violations = check_graph(graph.clone(), critical_path);
+        assert!(
+            violations.is_empty(),
+            "Seed {} (critical path: {}) failed: {:#?}\nGraph: {:#?}",
+            seed,
+            critical_path,
+            violations,
+            graph
+        );
+    }
+}
+
+#[test]
+fn newly_ready_system_waits_for_conflicting_system() {
+    // The third system becomes ready while the first one is still running
+    let graph = vec![
+        SystemDesc {
+            write: vec![0],
+            enabled: true,
+            work: Duration::from_millis(20),
+            ..SystemDesc::default()
+        },
+        SystemDesc {
+            write: vec![1],
+            enabled: true,
+            ..SystemDesc::default()
+        },
+        SystemDesc {
+            write: vec![0],
+            dependencies: vec![1],
+            enabled: true,
+            ..SystemDesc::default()
+        },
+    ];
+
+    let violations = check_graph(graph, false);
+    assert!(violations.is_empty(), "{:#?}", violations);
+}
+
+#[test]
+fn disabled_systems_keep_transitive_order() {
+    // The last system only reaches the slow first one through the disabled middle one
+    let graph = vec![
+        SystemDesc {
+            enabled: true,
+            work: Duration::from_millis(20),
+            ..SystemDesc::default()
+        },
+        SystemDesc {
+            dependencies: vec![0],
+            enabled: false,
+            ..SystemDesc::default()
+        },
+        SystemDesc {
+            dependencies: vec![1],
+            enabled: true,
+            ..SystemDesc::default()
+        },
+    ];
+
+    assert_eq!(ancestors(&graph), vec![vec![], vec![0], vec![0, 1]]);
+    let violations = check_graph(graph, false);
+    assert!(violations.is_empty(), "{:#?}", violations);
+}
@@ -0,0 +1,863 @@
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::time::{Duration, Instant};
+
+pub mod condition;
+#[cfg(test)]
+mod fuzz;
+pub mod profile;
+mod schedule;
+
+use crate::{archetype::Archetype, system::System, world::World};
+
+use self::{
+    condition::RunCondition,
+    profile::{FrameProfile, Profiler},
+    schedule::Schedule,
+};
+
+/// Maximum number of systems allowed in a single stage of a disaptcher.
+pub const MAX_SYSTEMS: usize = 128;
+
+/// A function with exclusive access to the world that runs at the end of a stage.
+type Flush = Box<dyn FnMut(&mut World)>;
+
+/// The dispatcher is where systems exist and is responsible for scheduling systems optimally.
+/// This is where the brunt of the logic for parallelization is going to go.
+///
+/// Systems are split into stages that run one after another. Each stage has its own dependency
+/// graph and is scheduled independently of the others.
+pub struct Dispatcher {
+    /// One schedule per stage, in the order the stages run.
+    schedules: Vec<Schedule>,
+    /// Flush functions to run at the end of each stage.
+    flushes: Vec<Vec<Flush>>,
+    thread_pool: ThreadPool,
+    fixed: FixedTimestep,
+    /// When `run` was last called. Used to advance the fixed timestep.
+    last_run: Option<Instant>,
+    /// Records timings of systems and the scheduler while profiling is enabled.
+    profiler: Option<Profiler>,
+    /// Run every system on the calling thread in a reproducible order.
+    deterministic: bool,
+}
+
+/// A stage of the dispatcher. Stages run in the order they are declared, and every system in a
+/// stage finishes before the next stage begins.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    PreUpdate,
+    /// Runs zero or more times per run of the dispatcher so that it runs once per fixed amount
+    /// of elapsed time.
+    FixedUpdate,
+    Update,
+    PostUpdate,
+    Render,
+}
+
+/// Keeps track of how many times the fixed update stage must run.
+struct FixedTimestep {
+    /// Amount of time a single run of the fixed update stage represents.
+    step: Duration,
+    /// Elapsed time that hasn't been consumed by the fixed update stage yet.
+    accumulator: Duration,
+    /// Most times the fixed update stage may run per run of the dispatcher. `None` if unbounded.
+    max_steps: Option<usize>,
+}
+
+/// If you are unfamiliar with the builder pattern, considering taking a look at this link:
+/// https://rust-unofficial.github.io/patterns/patterns/creational/builder.html
+pub struct DispatcherBuilder {
+    schedules: Vec<Schedule>,
+    flushes: Vec<Vec<Flush>>,
+    thread_count: usize,
+    fixed_step: Duration,
+    max_fixed_steps: Option<usize>,
+    /// Number of runs the profiler remembers. `None` if profiling is disabled.
+    profiling: Option<usize>,
+    deterministic: bool,
+}
+
+/// Unique ID of a system within a dispatcher. The ID is invalidated when the system is removed,
+/// and may be reused by a system added afterwards.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemId {
+    stage: Stage,
+    idx: usize,
+}
+
+impl Stage {
+    /// Every stage in the order they run.
+    pub const ALL: [Stage; 5] = [
+        Stage::PreUpdate,
+        Stage::FixedUpdate,
+        Stage::Update,
+        Stage::PostUpdate,
+        Stage::Render,
+    ];
+
+    #[inline]
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl SystemId {
+    /// The stage the system belongs to.
+    #[inline]
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+}
+
+impl Dispatcher {
+    #[inline]
+    pub fn builder() -> DispatcherBuilder {
+        DispatcherBuilder::new()
+    }
+
+    /// Runs every stage within the dispatcher using a given world. The fixed update stage is
+    /// advanced by the time elapsed since the previous call to `run`.
+    pub fn run(&mut self, world: &mut World) {
+        let now = Instant::now();
+        let delta = match self.last_run {
+            Some(last_run) => now.duration_since(last_run),
+            None => Duration::ZERO,
+        };
+        self.last_run = Some(now);
+
+        self.run_with_delta(world, delta);
+    }
+
+    /// Runs every stage within the dispatcher using a given world, advancing the fixed update
+    /// stage by `delta`.
+    pub fn run_with_delta(&mut self, world: &mut World, delta: Duration) {
+        let mut frame = self
+            .profiler
+            .as_ref()
+            .map(|_| FrameProfile::new(Instant::now()));
+
+        // Swap event buffers so events from two runs ago are dropped
+        world.events.update();
+
+        self.fixed.accumulator += delta;
+
+        for stage in Stage::ALL {
+            let schedule = &mut self.schedules[stage.index()];
+
+            let runs = if stage == Stage::FixedUpdate {
+                let mut runs = 0;
+                while self.fixed.accumulator >= self.fixed.step {
+                    if Some(runs) == self.fixed.max_steps {
+                        // Catching up would only fall further behind, so whole steps are dropped
+                        let step = self.fixed.step.as_nanos();
+                        let left = self.fixed.accumulator.as_nanos() % step;
+                        self.fixed.accumulator = Duration::from_nanos(left as u64);
+                        break;
+                    }
+                    self.fixed.accumulator -= self.fixed.step;
+                    runs += 1;
+                }
+                runs
+            } else {
+                1
+            };
+
+            for _ in 0..runs {
+                if self.deterministic {
+                    schedule.run_deterministic(world, frame.as_mut());
+                } else {
+                    schedule.run(world, &self.thread_pool, frame.as_mut());
+                }
+            }
+
+            // Every system in the stage is finished, so we have exclusive access to the world
+            for flush in &mut self.flushes[stage.index()] {
+                flush(world);
+            }
+        }
+
+        if let (Some(profiler), Some(mut frame)) = (&mut self.profiler, frame) {
+            frame.finished = Instant::now();
+            profiler.record(frame);
+        }
+    }
+
+    /// Adds a new system to the update stage of the dispatcher. Returns a unique ID for the
+    /// system to define dependencies.
+    #[inline]
+    pub fn add_system<S: System + Send + 'static>(
+        &mut self,
+        system: S,
+        dependencies: &[SystemId],
+    ) -> SystemId {
+        self.add_system_in_stage(Stage::Update, system, dependencies)
+    }
+
+    /// Adds a new system to a particular stage of the dispatcher. Returns a unique ID for the
+    /// system to define dependencies. Systems may only depend on systems within the same stage.
+    pub fn add_system_in_stage<S: System + Send + 'static>(
+        &mut self,
+        stage: Stage,
+        system: S,
+        dependencies: &[SystemId],
+    ) -> SystemId {
+        let dependencies = dependency_indices(stage, dependencies);
+
+        SystemId {
+            stage,
+            idx: self.schedules[stage.index()].add_system(system, &dependencies, false),
+        }
+    }
+
+    /// Adds a new system to the update stage of the dispatcher. When scheduling, the system is
+    /// treated as also reading and writing the data in the provided archetypes. See
+    /// `DispatcherBuilder::with_system_accessing`.
+    #[inline]
+    pub fn add_system_accessing<S: System + Send + 'static>(
+        &mut self,
+        system: S,
+        read: &Archetype,
+        write: &Archetype,
+        dependencies: &[SystemId],
+    ) -> SystemId {
+        self.add_system_accessing_in_stage(Stage::Update, system, read, write, dependencies)
+    }
+
+    /// Adds a new system to a particular stage of the dispatcher. See `add_system_accessing`.
+    pub fn add_system_accessing_in_stage<S: System + Send + 'static>(
+        &mut self,
+        stage: Stage,
+        system: S,
+        read: &Archetype,
+        write: &Archetype,
+        dependencies: &[SystemId],
+    ) -> SystemId {
+        let dependencies = dependency_indices(stage, dependencies);
+
+        SystemId {
+            stage,
+            idx: self.schedules[stage.index()].add_system_accessing(
+                system,
+                read,
+                write,
+                &dependencies,
+                false,
+            ),
+        }
+    }
+
+    /// Adds a system that can't be sent to other threads to the update stage of the dispatcher.
+    /// The system always runs on the thread that calls `Dispatcher::run`.
+    #[inline]
+    pub fn add_local_system<S: System + 'static>(
+        &mut self,
+        system: S,
+        dependencies: &[SystemId],
+    ) -> SystemId {
+        self.add_local_system_in_stage(Stage::Update, system, dependencies)
+    }
+
+    /// Adds a system that can't be sent to other threads to a particular stage of the
+    /// dispatcher. The system always runs on the thread that calls `Dispatcher::run`.
+    pub fn add_local_system_in_stage<S: System + 'static>(
+        &mut self,
+        stage: Stage,
+        system: S,
+        dependencies: &[SystemId],
+    ) -> SystemId {
+        let dependencies = dependency_indices(stage, dependencies);
+
+        SystemId {
+            stage,
+            idx: self.schedules[stage.index()].add_system(system, &dependencies, true),
+        }
+    }
+
+    /// Removes a system from the dispatcher. Systems that depended on the removed system inherit
+    /// its dependencies.
+    ///
+    /// Panics if the system was already removed.
+    #[inline]
+    pub fn remove_system(&mut self, system: SystemId) {
+        self.schedules[system.stage.index()].remove_system(system.idx);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.remove_system(system);
+        }
+    }
+
+    /// Enables or disables a system. Disabled systems don't run, but still count as finished so
+    /// their dependents are released.
+    #[inline]
+    pub fn set_enabled(&mut self, system: SystemId, enabled: bool) {
+        self.schedules[system.stage.index()].set_enabled(system.idx, enabled);
+    }
+
+    /// Adds a run condition to a system. The system only runs if all of its conditions pass.
+    #[inline]
+    pub fn run_if(&mut self, system: SystemId, condition: impl RunCondition + 'static) {
+        self.schedules[system.stage.index()].add_condition(system.idx, Box::new(condition));
+    }
+
+    /// Declares how long a system takes to run. When preferring the critical path, declared costs
+    /// are used instead of measured run times.
+    #[inline]
+    pub fn set_cost(&mut self, system: SystemId, cost: Duration) {
+        self.schedules[system.stage.index()].set_cost(system.idx, cost);
+    }
+
+    /// Enables or disables preferring the critical path. See `DispatcherBuilder::critical_path`.
+    pub fn set_critical_path(&mut self, critical_path: bool) {
+        for schedule in &mut self.schedules {
+            schedule.set_critical_path(critical_path);
+        }
+    }
+
+    /// Starts recording the timings of every run. The profiler remembers the last `history` runs.
+    /// Does nothing if profiling is already enabled.
+    pub fn enable_profiling(&mut self, history: usize) {
+        if self.profiler.is_none() {
+            self.profiler = Some(Profiler::new(history));
+        }
+    }
+
+    /// Stops recording timings and returns everything that was recorded.
+    #[inline]
+    pub fn disable_profiling(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    /// Returns `None` if profiling is disabled.
+    #[inline]
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Returns `None` if profiling is disabled.
+    #[inline]
+    pub fn profiler_mut(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_mut()
+    }
+
+    /// Amount of time a single run of the fixed update stage represents.
+    #[inline]
+    pub fn fixed_step(&self) -> Duration {
+        self.fixed.step
+    }
+
+    /// Fraction of a fixed step that has elapsed but hasn't been simulated yet. Useful for
+    /// interpolating between fixed updates when rendering.
+    #[inline]
+    pub fn fixed_overstep(&self) -> f32 {
+        self.fixed.accumulator.as_secs_f32() / self.fixed.step.as_secs_f32()
+    }
+}
+
+impl Default for DispatcherBuilder {
+    fn default() -> Self {
+        Self {
+            schedules: Stage::ALL
+                .iter()
+                .map(|stage| Schedule::new(*stage))
+                .collect(),
+            flushes: Stage::ALL.iter().map(|_| Vec::default()).collect(),
+            thread_count: 1,
+            fixed_step: Duration::from_secs(1) / 60,
+            max_fixed_steps: None,
+            profiling: None,
+            deterministic: false,
+        }
+    }
+}
+
+impl DispatcherBuilder {
+    #[inline]
+    pub fn new() -> Self {
+        DispatcherBuilder::default()
+    }
+
+    pub fn thread_count(mut self, thread_count: usize) -> Self {
+        self.thread_count = thread_count;
+        self
+    }
+
+    /// Sets the amount of time a single run of the fixed update stage represents.
+    pub fn fixed_step(mut self, step: Duration) -> Self {
+        assert!(!step.is_zero());
+        self.fixed_step = step;
+        self
+    }
+
+    /// Limits how many times the fixed update stage runs per run of the dispatcher. When more
+    /// time has elapsed than the limit covers, the extra whole steps are dropped instead of being
+    /// caught up on later, so a slow fixed update stage can't fall further behind every run. The
+    /// stage runs as many times as needed by default.
+    pub fn max_fixed_steps(mut self, max: usize) -> Self {
+        assert_ne!(max, 0);
+        self.max_fixed_steps = Some(max);
+        self
+    }
+
+    /// When enabled, systems run one at a time on the thread that calls `Dispatcher::run`. The
+    /// order only depends on the dependency graph and the order systems were added in, which
+    /// makes runs reproducible across machines. The thread pool and schedule cache are unused.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// When enabled, the scheduler prefers running systems with the longest chains of dependents
+    /// ahead of them, instead of simply running as many systems in parallel as possible. This
+    /// keeps a long chain of dependencies from being starved by many independent systems.
+    ///
+    /// Chains are weighed using the declared cost of each system, or its measured run time if it
+    /// doesn't have one.
+    pub fn critical_path(mut self, critical_path: bool) -> Self {
+        for schedule in &mut self.schedules {
+            schedule.set_critical_path(critical_path);
+        }
+        self
+    }
+
+    /// Enables profiling from the first run. The profiler remembers the last `history` runs.
+    pub fn profiling(mut self, history: usize) -> Self {
+        self.profiling = Some(history);
+        self
+    }
+
+    /// Adds a new system to the update stage of the dispatcher. Returns a unique ID for the
+    /// system to define dependencies.
+    #[inline]
+    pub fn with_system<S: System + Send + 'static>(
+        &mut self,
+        system: S,
+        dependencies: &[SystemId],
+    ) -> SystemId {
+        self.with_system_in_stage(Stage::Update, system, dependencies)
+    }
+
+    /// Adds a new system to a particular stage of the dispatcher. Returns a unique ID for the
+    /// system to define dependencies. Systems may only depend on systems within the same stage.
+    pub fn with_system_in_stage<S: System + Send + 'static>(
+        &mut self,
+        stage: Stage,
+        system: S,
+        dependencies: &[SystemId],
+    ) -> SystemId {
+        let dependencies = dependency_indices(stage, dependencies);
+
+        SystemId {
+            stage,
+            idx: self.schedules[stage.index()].add_system(system, &dependencies, false),
+        }
+    }
+
+    /// Adds a new system to the update stage of the dispatcher. When scheduling, the system is
+    /// treated as also reading and writing the data in the provided archetypes, which is useful
+    /// when that data is only known at runtime.
+    #[inline]
+    pub fn with_system_accessing<S: System + Send + 'static>(
+        &mut self,
+        system: S,
+        read: &Archetype,
+        write: &Archetype,
+        dependencies: &[SystemId],
+    ) -> SystemId {
+        self.with_system_accessing_in_stage(Stage::Update, system, read, write, dependencies)
+    }
+
+    /// Adds a new system to a particular stage of the dispatcher. See `with_system_accessing`.
+    pub fn with_system_accessing_in_stage<S: System + Send + 'static>(
+        &mut self,
+        stage: Stage,
+        system: S,
+        read: &Archetype,
+        write: &Archetype,
+        dependencies: &[SystemId],
+    ) -> SystemId {
+        let dependencies = dependency_indices(stage, dependencies);
+
+        SystemId {
+            stage,
+            idx: self.schedules[stage.index()].add_system_accessing(
+                system,
+                read,
+                write,
+                &dependencies,
+                false,
+            ),
+        }
+    }
+
+    /// Adds a system that can't be sent to other threads to the update stage of the dispatcher.
+    /// The system always runs on the thread that calls `Dispatcher::run`.
+    #[inline]
+    pub fn with_local_system<S: System + 'static>(
+        &mut self,
+        system: S,
+        dependencies: &[SystemId],
+    ) -> SystemId {
+        self.with_local_system_in_stage(Stage::Update, system, dependencies)
+    }
+
+    /// Adds a system that can't be sent to other threads to a particular stage of the
+    /// dispatcher. The system always runs on the thread that calls `Dispatcher::run`.
+    pub fn with_local_system_in_stage<S: System + 'static>(
+        &mut self,
+        stage: Stage,
+        system: S,
+        dependencies: &[SystemId],
+    ) -> SystemId {
+        let dependencies = dependency_indices(stage, dependencies);
+
+        SystemId {
+            stage,
+            idx: self.schedules[stage.index()].add_system(system, &dependencies, true),
+        }
+    }
+
+    /// Adds a function that runs at the end of a stage with exclusive access to the world. This
+    /// is the point where changes deferred by systems in the stage should be applied.
+    pub fn with_flush(&mut self, stage: Stage, flush: impl FnMut(&mut World) + 'static) {
+        self.flushes[stage.index()].push(Box::new(flush));
+    }
+
+    /// Adds a run condition to a system. The system only runs if all of its conditions pass. When
+    /// a system is skipped, it still counts as finished so its dependents are released.
+    pub fn run_if(&mut self, system: SystemId, condition: impl RunCondition + 'static) {
+        self.schedules[system.stage.index()].add_condition(system.idx, Box::new(condition));
+    }
+
+    /// Declares how long a system takes to run. See `DispatcherBuilder::critical_path`.
+    pub fn cost(&mut self, system: SystemId, cost: Duration) {
+        self.schedules[system.stage.index()].set_cost(system.idx, cost);
+    }
+
+    pub fn build(self) -> Dispatcher {
+        Dispatcher {
+            schedules: self.schedules,
+            flushes: self.flushes,
+            thread_pool: ThreadPoolBuilder::new()
+                .num_threads(self.thread_count)
+                .build()
+                .unwrap(),
+            fixed: FixedTimestep {
+                step: self.fixed_step,
+                accumulator: Duration::ZERO,
+                max_steps: self.max_fixed_steps,
+            },
+            last_run: None,
+            profiler: self.profiling.map(Profiler::new),
+            deterministic: self.deterministic,
+        }
+    }
+}
+
+/// Helper function that converts the IDs of dependencies into indices within the schedule of a
+/// stage.
+fn dependency_indices(stage: Stage, dependencies: &[SystemId]) -> Vec<usize> {
+    dependencies
+        .iter()
+        .map(|dependency| {
+            assert_eq!(
+                dependency.stage, stage,
+                "Systems can only depend on systems in the same stage"
+            );
+            dependency.idx
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dispatcher, Stage};
+    use crate::{
+        archetype::Archetype,
+        component::{
+            filter::{Read, Write},
+            registry::ComponentId,
+            Component,
+        },
+        system::{dynamic::DynamicQuery, query::QueryGenerator, System},
+        world::World,
+    };
+    use std::{
+        cell::RefCell,
+        rc::Rc,
+        sync::{Arc, Mutex},
+        thread::ThreadId,
+        time::Duration,
+    };
+
+    struct Logger(Stage, Arc<Mutex<Vec<String>>>);
+
+    impl System for Logger {
+        type Components = ();
+
+        fn tick(&mut self, _: QueryGenerator) {
+            self.1.lock().unwrap().push(format!("{:?}", self.0));
+        }
+    }
+
+    #[test]
+    fn stages_run_in_order() {
+        let mut world = World::new();
+        let log = Arc::new(Mutex::new(Vec::default()));
+
+        // Add the stages out of order
+        let mut builder = Dispatcher::builder().fixed_step(Duration::from_millis(10));
+        for stage in Stage::ALL.iter().rev() {
+            builder.with_system_in_stage(*stage, Logger(*stage, log.clone()), &[]);
+        }
+
+        let flush_log = log.clone();
+        builder.with_flush(Stage::PostUpdate, move |_| {
+            flush_log.lock().unwrap().push(String::from("Flush"));
+        });
+
+        let mut dispatcher = builder.build();
+
+        // 25ms is enough for two fixed steps with 5ms left over
+        dispatcher.run_with_delta(&mut world, Duration::from_millis(25));
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "PreUpdate",
+                "FixedUpdate",
+                "FixedUpdate",
+                "Update",
+                "PostUpdate",
+                "Flush",
+                "Render"
+            ]
+        );
+        assert!((dispatcher.fixed_overstep() - 0.5).abs() < 0.001);
+
+        // The left over 5ms plus another 5ms is enough for one more fixed step
+        log.lock().unwrap().clear();
+        dispatcher.run_with_delta(&mut world, Duration::from_millis(5));
+        assert_eq!(
+            log.lock()
+                .unwrap()
+                .iter()
+                .filter(|stage| *stage == "FixedUpdate")
+                .count(),
+            1
+        );
+        assert!(dispatcher.fixed_overstep() < 0.001);
+    }
+
+    #[test]
+    fn fixed_steps_are_capped() {
+        let mut world = World::new();
+        let log = Arc::new(Mutex::new(Vec::default()));
+
+        let mut builder = Dispatcher::builder()
+            .fixed_step(Duration::from_millis(10))
+            .max_fixed_steps(3);
+        builder.with_system_in_stage(
+            Stage::FixedUpdate,
+            Logger(Stage::FixedUpdate, log.clone()),
+            &[],
+        );
+        let mut dispatcher = builder.build();
+
+        // 75ms covers seven steps, but only three run and the rest is dropped
+        dispatcher.run_with_delta(&mut world, Duration::from_millis(75));
+        assert_eq!(log.lock().unwrap().len(), 3);
+        assert!((dispatcher.fixed_overstep() - 0.5).abs() < 0.001);
+
+        log.lock().unwrap().clear();
+        dispatcher.run_with_delta(&mut world, Duration::from_millis(5));
+        assert_eq!(log.lock().unwrap().len(), 1);
+        assert!(dispatcher.fixed_overstep() < 0.001);
+    }
+
+    struct Named(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl System for Named {
+        type Components = ();
+
+        fn tick(&mut self, _: QueryGenerator) {
+            self.1.lock().unwrap().push(String::from(self.0));
+        }
+    }
+
+    #[test]
+    fn modify_systems_at_runtime() {
+        let mut world = World::new();
+        let log = Arc::new(Mutex::new(Vec::default()));
+
+        let mut builder = Dispatcher::builder().thread_count(4);
+        let a = builder.with_system(Named("A", log.clone()), &[]);
+        let b = builder.with_system(Named("B", log.clone()), &[a]);
+        builder.with_system(Named("C", log.clone()), &[b]);
+        let mut dispatcher = builder.build();
+
+        dispatcher.run(&mut world);
+        assert_eq!(*log.lock().unwrap(), vec!["A", "B", "C"]);
+
+        // C must still wait on A after B is removed
+        dispatcher.remove_system(b);
+        log.lock().unwrap().clear();
+        dispatcher.run(&mut world);
+        assert_eq!(*log.lock().unwrap(), vec!["A", "C"]);
+
+        // D reuses the slot of B
+        let d = dispatcher.add_system(Named("D", log.clone()), &[a]);
+        assert_eq!(d, b);
+
+        dispatcher.set_enabled(a, false);
+        log.lock().unwrap().clear();
+        dispatcher.run(&mut world);
+        let mut names = log.lock().unwrap().clone();
+        names.sort();
+        assert_eq!(names, vec!["C", "D"]);
+
+        dispatcher.set_enabled(a, true);
+        log.lock().unwrap().clear();
+        dispatcher.run(&mut world);
+        assert_eq!(log.lock().unwrap()[0], "A");
+        assert_eq!(log.lock().unwrap().len(), 3);
+    }
+
+    /// Keeps track of the threads it ran on. Not `Send` because of the `Rc`.
+    struct MainThreadOnly(Rc<RefCell<Vec<ThreadId>>>);
+
+    impl System for MainThreadOnly {
+        type Components = ();
+
+        fn tick(&mut self, _: QueryGenerator) {
+            self.0.borrow_mut().push(std::thread::current().id());
+        }
+    }
+
+    #[test]
+    fn local_systems_run_on_calling_thread() {
+        let mut world = World::new();
+        let log = Arc::new(Mutex::new(Vec::default()));
+        let threads = Rc::new(RefCell::new(Vec::default()));
+
+        let mut builder = Dispatcher::builder().thread_count(4);
+        let a = builder.with_system(Named("A", log.clone()), &[]);
+        let local = builder.with_local_system(MainThreadOnly(threads.clone()), &[a]);
+        builder.with_system(Named("B", log.clone()), &[local]);
+        builder.with_local_system_in_stage(Stage::Render, MainThreadOnly(threads.clone()), &[]);
+        let mut dispatcher = builder.build();
+
+        for _ in 0..4 {
+            dispatcher.run(&mut world);
+        }
+
+        assert_eq!(threads.borrow().len(), 8);
+        assert!(threads
+            .borrow()
+            .iter()
+            .all(|id| *id == std::thread::current().id()));
+        assert_eq!(log.lock().unwrap().len(), 8);
+    }
+
+    struct Value(u64);
+
+    impl Component for Value {}
+
+    /// Applies a step of a linear congruential generator to every value.
+    struct Scramble(u64);
+
+    impl System for Scramble {
+        type Components = (Write<Value>,);
+
+        fn tick(&mut self, gen: QueryGenerator) {
+            for (_, (value,)) in gen.create::<(Write<Value>,)>() {
+                value.0 = value
+                    .0
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(self.0);
+            }
+        }
+    }
+
+    struct Collect(Arc<Mutex<Vec<u64>>>);
+
+    impl System for Collect {
+        type Components = (Read<Value>,);
+
+        fn tick(&mut self, gen: QueryGenerator) {
+            let mut values = self.0.lock().unwrap();
+            values.clear();
+            values.extend(gen.create::<(Read<Value>,)>().map(|(_, (value,))| value.0));
+        }
+    }
+
+    fn simulate(deterministic: bool, log: Arc<Mutex<Vec<String>>>) -> Vec<u64> {
+        let mut world = World::new();
+        world.create((vec![Value(1), Value(2), Value(3)],));
+
+        let values = Arc::new(Mutex::new(Vec::default()));
+
+        let mut builder = Dispatcher::builder()
+            .thread_count(4)
+            .deterministic(deterministic);
+        let first = builder.with_system(Scramble(1), &[]);
+        builder.with_system(Named("B", log.clone()), &[]);
+        let second = builder.with_system(Scramble(7), &[first]);
+        builder.with_system(Named("A", log.clone()), &[]);
+        builder.with_system(Collect(values.clone()), &[second]);
+        let mut dispatcher = builder.build();
+
+        for _ in 0..10 {
+            dispatcher.run(&mut world);
+        }
+
+        let values = values.lock().unwrap().clone();
+        values
+    }
+
+    #[test]
+    fn deterministic_matches_parallel() {
+        let log = Arc::new(Mutex::new(Vec::default()));
+        let deterministic = simulate(true, log.clone());
+
+        // Systems without dependencies run in the order they were added
+        assert_eq!(log.lock().unwrap()[..2], ["B", "A"]);
+
+        assert_eq!(deterministic, simulate(false, log));
+        assert_eq!(deterministic.len(), 3);
+    }
+
+    /// Doubles every value through a dynamic query, so it only works if its access was declared.
+    struct DoubleDynamic;
+
+    impl System for DoubleDynamic {
+        type Components = ();
+
+        fn tick(&mut self, gen: QueryGenerator) {
+            let query = DynamicQuery::new().write(ComponentId::of::<Value>());
+            let mut rows = gen.create_dynamic(&query);
+            while let Some((_, components)) = rows.next_row() {
+                unsafe { (*components[0].cast::<Value>()).0 *= 2 };
+            }
+        }
+    }
+
+    #[test]
+    fn add_local_and_accessing_systems_at_runtime() {
+        let mut world = World::new();
+        world.create((vec![Value(1), Value(2)],));
+        let values = Arc::new(Mutex::new(Vec::default()));
+        let threads = Rc::new(RefCell::new(Vec::default()));
+
+        let mut dispatcher = Dispatcher::builder().thread_count(4).build();
+        let mut write = Archetype::default();
+        write.add_component_by_id(ComponentId::of::<Value>());
+        let double =
+            dispatcher.add_system_accessing(DoubleDynamic, &Archetype::default(), &write, &[]);
+        let local = dispatcher.add_local_system(MainThreadOnly(threads.clone()), &[double]);
+        dispatcher.add_system(Collect(values.clone()), &[local]);
+        dispatcher.run(&mut world);
+
+        let mut collected = values.lock().unwrap().clone();
+        collected.sort();
+        assert_eq!(collected, vec![2, 4]);
+        assert_eq!(*threads.borrow(), vec![std::thread::current().id()]);
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use super::{Stage, SystemId};
+
+/// Records what the dispatcher did during its most recent runs.
+pub struct Profiler {
+    /// Most recently recorded runs, oldest first.
+    frames: VecDeque<FrameProfile>,
+    /// Maximum number of runs to keep in `frames`.
+    history: usize,
+    /// Statistics for every system that has been recorded.
+    stats: HashMap<SystemId, SystemStats>,
+    scheduler: SchedulerStats,
+    /// Time stamps in exported traces are relative to this.
+    epoch: Instant,
+}
+
+/// Everything recorded during a single call to `Dispatcher::run`.
+#[derive(Debug, Clone)]
+pub struct FrameProfile {
+    pub started: Instant,
+    pub finished: Instant,
+    /// Every system that ran, in the order they finished.
+    pub systems: Vec<SystemTiming>,
+    pub scheduler: SchedulerStats,
+}
+
+/// When a system ran during a run of the dispatcher.
+#[derive(Debug, Clone)]
+pub struct SystemTiming {
+    pub system: SystemId,
+    pub name: &'static str,
+    /// When the scheduler decided the system could run.
+    pub scheduled: Instant,
+    pub started: Instant,
+    pub finished: Instant,
+    /// Index of the worker thread the system ran on. `None` if the system ran on the thread that
+    /// called `Dispatcher::run`.
+    pub thread: Option<usize>,
+}
+
+/// How much time the scheduler spent picking systems to run.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SchedulerStats {
+    /// Time spent looking for a set of systems in the schedule cache.
+    pub cache_lookup: Duration,
+    /// Time spent finding a set of systems to run using Bron-Kerbosch when the cache missed.
+    pub bron_kerbosch: Duration,
+    pub cache_hits: usize,
+    pub cache_misses: usize,
+}
+
+/// Aggregated run times of a single system.
+#[derive(Debug, Clone)]
+pub struct SystemStats {
+    pub name: &'static str,
+    pub runs: usize,
+    pub total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl Profiler {
+    /// Creates a profiler that remembers the last `history` runs of the dispatcher. Statistics
+    /// are aggregated over every run, regardless of the history.
+    pub fn new(history: usize) -> Self {
+        Self {
+            frames: VecDeque::with_capacity(history),
+            history,
+            stats: HashMap::default(),
+            scheduler: SchedulerStats::default(),
+            epoch: Instant::now(),
+        }
+    }
+
+    /// Returns an iterator over the recorded runs, oldest first.
+    #[inline]
+    pub fn frames(&self) -> impl Iterator<Item = &FrameProfile> {
+        self.frames.iter()
+    }
+
+    #[inline]
+    pub fn last_frame(&self) -> Option<&FrameProfile> {
+        self.frames.back()
+    }
+
+    /// Statistics for a single system. Returns `None` if the system hasn't run while profiling.
+    #[inline]
+    pub fn system_stats(&self, system: SystemId) -> Option<&SystemStats> {
+        self.stats.get(&system)
+    }
+
+    /// Returns an iterator over the statistics of every system that has run while profiling.
+    #[inline]
+    pub fn all_system_stats(&self) -> impl Iterator<Item = (SystemId, &SystemStats)> {
+        self.stats.iter().map(|(id, stats)| (*id, stats))
+    }
+
+    /// Scheduler statistics summed over every recorded run.
+    #[inline]
+    pub fn scheduler_stats(&self) -> SchedulerStats {
+        self.scheduler
+    }
+
+    /// Forgets every recorded run and statistic.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.stats.clear();
+        self.scheduler = SchedulerStats::default();
+    }
+
+    /// Forgets the statistics of a removed system, since its ID may be handed out again.
+    pub(crate) fn remove_system(&mut self, system: SystemId) {
+        self.stats.remove(&system);
+    }
+
+    pub(crate) fn record(&mut self, frame: FrameProfile) {
+        for timing in &frame.systems {
+            let duration = timing.finished.duration_since(timing.started);
+            let stats = self.stats.entry(timing.system).or_insert(SystemStats {
+                name: timing.name,
+                runs: 0,
+                total: Duration::ZERO,
+                min: Duration::MAX,
+                max: Duration::ZERO,
+            });
+
+            stats.runs += 1;
+            stats.total += duration;
+            stats.min = stats.min.min(duration);
+            stats.max = stats.max.max(duration);
+        }
+
+        self.scheduler.cache_lookup += frame.scheduler.cache_lookup;
+        self.scheduler.bron_kerbosch += frame.scheduler.bron_kerbosch;
+        self.scheduler.cache_hits += frame.scheduler.cache_hits;
+        self.scheduler.cache_misses += frame.scheduler.cache_misses;
+
+        if self.history == 0 {
+            return;
+        }
+
+        if self.frames.len() == self.history {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    /// Writes the recorded runs in the Chrome Trace Event format, which can be loaded into
+    /// `chrome://tracing` or Perfetto.
+    ///
+    /// Runs of the dispatcher and systems on the calling thread are shown on thread 0. Systems on
+    /// worker `N` are shown on thread `N + 1`.
+    pub fn write_chrome_trace(&self, mut out: impl Write) -> io::Result<()> {
+        let mut threads = vec![None];
+
+        write!(out, "{{\"traceEvents\":[")?;
+
+        let mut first = true;
+        for frame in &self.frames {
+            if !first {
+                write!(out, ",")?;
+            }
+            first = false;
+
+            write!(
+                out,
+                "{{\"name\":\"Dispatcher::run\",\"cat\":\"dispatcher\",\"ph\":\"X\",\"ts\":{:.3},\
+                 \"dur\":{:.3},\"pid\":0,\"tid\":0,\"args\":{{\"cache_lookup_us\":{:.3},\
+                 \"bron_kerbosch_us\":{:.3},\"cache_hits\":{},\"cache_misses\":{}}}}}",
+                self.micros(frame.started),
+                micros(frame.finished.duration_since(frame.started)),
+                micros(frame.scheduler.cache_lookup),
+                micros(frame.scheduler.bron_kerbosch),
+                frame.scheduler.cache_hits,
+                frame.scheduler.cache_misses,
+            )?;
+
+            for timing in &frame.systems {
+                let tid = timing.thread.map(|thread| thread + 1).unwrap_or(0);
+                if !threads.contains(&timing.thread) {
+                    threads.push(timing.thread);
+                }
+
+                write!(
+                    out,
+                    ",{{\"name\":\"{}\",\"cat\":\"system\",\"ph\":\"X\",\"ts\":{:.3},\
+                     \"dur\":{:.3},\"pid\":0,\"tid\":{},\"args\":{{\"stage\":\"{:?}\",\
+                     \"scheduled_us\":{:.3},\"wait_us\":{:.3}}}}}",
+                    escape(timing.name),
+                    self.micros(timing.started),
+                    micros(timing.finished.duration_since(timing.started)),
+                    tid,
+                    timing.system.stage(),
+                    self.micros(timing.scheduled),
+                    micros(timing.started.duration_since(timing.scheduled)),
+                )?;
+            }
+        }
+
+        // Name the threads
+        for thread in threads {
+            if !first {
+                write!(out, ",")?;
+            }
+            first = false;
+
+            let (tid, name) = match thread {
+                Some(thread) => (thread + 1, format!("Worker {}", thread)),
+                None => (0, String::from("Main")),
+            };
+
+            write!(
+                out,
+                "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":{},\
+                 \"args\":{{\"name\":\"{}\"}}}}",
+                tid, name
+            )?;
+        }
+
+        write!(out, "],\"displayTimeUnit\":\"ms\"}}")
+    }
+
+    /// Number of microseconds between the epoch and the provided instant.
+    #[inline]
+    fn micros(&self, instant: Instant) -> f64 {
+        micros(instant.saturating_duration_since(self.epoch))
+    }
+}
+
+impl FrameProfile {
+    #[inline]
+    pub(crate) fn new(started: Instant) -> Self {
+        Self {
+            started,
+            finished: started,
+            systems: Vec::default(),
+            scheduler: SchedulerStats::default(),
+        }
+    }
+
+    /// Time the systems of a stage spent running summed together.
+    pub fn busy_time(&self, stage: Stage) -> Duration {
+        self.systems
+            .iter()
+            .filter(|timing| timing.system.stage() == stage)
+            .map(|timing| timing.finished.duration_since(timing.started))
+            .sum()
+    }
+}
+
+impl SystemStats {
+    #[inline]
+    pub fn mean(&self) -> Duration {
+        self.total / self.runs as u32
+    }
+}
+
+#[inline]
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000_000.0
+}
+
+/// Escapes a string so it can be put inside of a JSON string.
+fn escape(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        component::{
+            filter::{Read, Write},
+            Component,
+        },
+        dispatcher::{Dispatcher, Stage},
+        system::{query::QueryGenerator, System},
+        world::World,
+    };
+
+    struct Position;
+    struct Velocity;
+
+    impl Component for Position {}
+    impl Component for Velocity {}
+
+    struct Movement;
+
+    impl System for Movement {
+        type Components = (Write<Position>, Read<Velocity>);
+
+        fn tick(&mut self, _: QueryGenerator) {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    struct Drag;
+
+    impl System for Drag {
+        type Components = (Write<Velocity>,);
+
+        fn tick(&mut self, _: QueryGenerator) {}
+    }
+
+    #[test]
+    fn profile_runs() {
+        let mut world = World::new();
+
+        let mut builder = Dispatcher::builder().thread_count(2).profiling(2);
+        let movement = builder.with_system(Movement, &[]);
+        let drag = builder.with_system_in_stage(Stage::PostUpdate, Drag, &[]);
+        let mut dispatcher = builder.build();
+
+        for _ in 0..3 {
+            dispatcher.run(&mut world);
+        }
+
+        let profiler = dispatcher.profiler().unwrap();
+        assert_eq!(profiler.frames().count(), 2);
+
+        let frame = profiler.last_frame().unwrap();
+        assert_eq!(frame.systems.len(), 2);
+        for timing in &frame.systems {
+            assert!(timing.scheduled <= timing.started);
+            assert!(timing.started <= timing.finished);
+            assert!(frame.started <= timing.scheduled && timing.finished <= frame.finished);
+            assert!(timing.thread.is_some());
+        }
+        assert!(frame.busy_time(Stage::Update) >= std::time::Duration::from_millis(1));
+
+        let stats = profiler.system_stats(movement).unwrap();
+        assert_eq!(stats.runs, 3);
+        assert!(stats.name.ends_with("Movement"));
+        assert!(stats.min <= stats.mean() && stats.mean() <= stats.max);
+        assert_eq!(profiler.system_stats(drag).unwrap().runs, 3);
+
+        let scheduler = profiler.scheduler_stats();
+        assert_eq!(scheduler.cache_hits + scheduler.cache_misses, 6);
+        assert_eq!(scheduler.cache_misses, 2);
+
+        let mut trace = Vec::default();
+        profiler.write_chrome_trace(&mut trace).unwrap();
+        let trace = String::from_utf8(trace).unwrap();
+        assert!(trace.starts_with("{\"traceEvents\":["));
+        assert!(trace.ends_with("}"));
+        assert_eq!(trace.matches("\"cat\":\"system\"").count(), 4);
+        assert_eq!(trace.matches("Dispatcher::run").count(), 2);
+        assert!(trace.contains("Movement"));
+        assert!(trace.contains("thread_name"));
+    }
+
+    #[test]
+    fn removed_systems_lose_stats() {
+        let mut world = World::new();
+
+        let mut builder = Dispatcher::builder().thread_count(1).profiling(1);
+        let drag = builder.with_system(Drag, &[]);
+        let mut dispatcher = builder.build();
+        dispatcher.run(&mut world);
+
+        dispatcher.remove_system(drag);
+        let movement = dispatcher.add_system(Movement, &[]);
+        assert_eq!(movement, drag);
+        dispatcher.run(&mut world);
+
+        let stats = dispatcher
+            .profiler()
+            .unwrap()
+            .system_stats(movement)
+            .unwrap();
+        assert_eq!(stats.runs, 1);
+        assert!(stats.name.ends_with("Movement"));
+    }
+}
@@ -0,0 +1,1020 @@
+use bitvec::prelude::*;
+use crossbeam_channel::{Receiver, Sender};
+use rayon::ThreadPool;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    ops::BitAnd,
+    ops::{BitOr, BitXor, Index, IndexMut, Not},
+    ptr::NonNull,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    archetype::Archetype,
+    system::{GenericSystem, System, SystemAccess, SystemState},
+    world::World,
+};
+
+use super::{
+    condition::RunCondition,
+    profile::{FrameProfile, SystemTiming},
+    Stage, SystemId, MAX_SYSTEMS,
+};
+
+/// Bits which represent a set of systems.
+type SystemSet = BitArr!(for MAX_SYSTEMS);
+
+/// When a system started and finished running, and the index of the worker thread it ran on.
+type Timing = (Instant, Instant, Option<usize>);
+
+/// Cost of a system that hasn't declared a cost and hasn't been measured yet.
+const DEFAULT_COST: Duration = Duration::from_micros(1);
+
+/// How much the measured cost of a system has to change, in percent, before the system is ranked
+/// with the new cost.
+const RERANK_PERCENT: u32 = 25;
+
+/// A dependency graph of systems along with everything needed to run it in parallel. Each stage
+/// of the dispatcher has its own schedule.
+pub(crate) struct Schedule {
+    /// The stage the schedule runs in.
+    stage: Stage,
+    systems: SystemSlots,
+    /// Maps each system to a `SystemSet` of compatible systems.
+    compatibility: Vec<SystemSet>,
+    /// Cache that maps the sets of running and pending systems to the pending systems that should
+    /// start running. Each bit in the `BitArr` represents a system.
+    cache: HashMap<(SystemSet, SystemSet), Vec<usize>>,
+    cached_buffers: CachedBuffers,
+    /// Order systems run in when running deterministically. Empty if it needs to be recomputed.
+    order: Vec<usize>,
+    /// Prefer running systems on the critical path of the dependency graph.
+    critical_path: bool,
+    /// Indices of systems sorted by decreasing critical path length. Empty if it needs to be
+    /// recomputed.
+    priority: Vec<usize>,
+}
+
+/// Cached buffers so we don't have to reallocate.
+#[derive(Default)]
+struct CachedBuffers {
+    bron_kerbosch: Vec<SystemSet>,
+    to_remove: Vec<usize>,
+    /// Systems which are no longer waiting on dependencies, but haven't had their run conditions
+    /// checked yet.
+    ready: Vec<usize>,
+    pending: HashSet<usize>,
+    finished: Vec<usize>,
+    running: HashSet<usize>,
+    /// Main thread systems that are allowed to run, but haven't started yet.
+    local: Vec<usize>,
+}
+
+/// Holds the systems of a schedule. The index of a system never changes, and the slots of
+/// removed systems are reused by new ones.
+///
+/// Indexing a slot that doesn't hold a system panics.
+#[derive(Default)]
+struct SystemSlots {
+    slots: Vec<Option<SystemStage>>,
+    /// Indices of empty slots.
+    free: Vec<usize>,
+    /// Number of systems in the container.
+    len: usize,
+}
+
+/// Describes the state of a system in the dispatcher.
+struct SystemStage {
+    system: Box<dyn GenericSystem>,
+    /// Type name of the system.
+    name: &'static str,
+    write_types: Archetype,
+    all_types: Archetype,
+    /// Data kept for the system in between runs.
+    state: SystemState,
+    /// Predicates that must all pass for the system to run.
+    conditions: Vec<Box<dyn RunCondition>>,
+    /// Indicates the system failed one of its run conditions during the current run.
+    skipped: bool,
+    /// Disabled systems are skipped, but still release their dependents.
+    enabled: bool,
+    /// Local systems must run on the thread that calls `Schedule::run`.
+    local: bool,
+    /// When the system was last allowed to run. Only updated while profiling.
+    scheduled: Option<Instant>,
+    /// Cost declared by the user. Takes precedence over the measured cost.
+    cost: Option<Duration>,
+    /// Moving average of the run time of the system. Only updated when the schedule prefers the
+    /// critical path.
+    measured: Option<Duration>,
+    /// Measured cost the system was last ranked with. Measurements jitter from run to run, so it
+    /// only follows large changes to keep the critical paths, and with them the cache, stable.
+    ranked: Duration,
+    /// Cost of the most expensive chain of dependents starting with this system, including the
+    /// system itself.
+    critical_path: Duration,
+    /// Receiver that threads use to notify the main thread that the system has finished running.
+    /// Timing information is sent along while the system is timed.
+    finished: Receiver<Option<Timing>>,
+    /// Sender that threads use to notify the main thread that the system has finished running.
+    thread_sender: Sender<Option<Timing>>,
+    /// Indices of systems that we are dependent on.
+    dependencies: Vec<usize>,
+    /// Number of dependencies the system is waiting on currently.
+    waiting_on: usize,
+    /// Indices of systems that are dependent on us.
+    dependents: Vec<usize>,
+}
+
+/// Description for a thread of a system to run.
+struct SystemPacket {
+    /// System to run.
+    system: NonNull<dyn GenericSystem>,
+    /// Data the system must use.
+    state: *const SystemState,
+    /// World the system runs in.
+    world: *const World,
+    /// Sender that threads use to notify the main thread that a system has finished running.
+    thread_sender: Sender<Option<Timing>>,
+    /// Indicates the system should be timed.
+    timed: bool,
+}
+
+unsafe impl Send for SystemPacket {}
+
+impl Schedule {
+    pub fn new(stage: Stage) -> Self {
+        Self {
+            stage,
+            systems: SystemSlots::default(),
+            compatibility: Vec::default(),
+            cache: HashMap::default(),
+            cached_buffers: CachedBuffers::default(),
+            order: Vec::default(),
+            critical_path: false,
+            priority: Vec::default(),
+        }
+    }
+
+    /// Adds a new system to the schedule and returns its index. Dependencies are indices of
+    /// systems within this schedule. Local systems are never sent to the thread pool.
+    #[inline]
+    pub fn add_system<S: System + 'static>(
+        &mut self,
+        system: S,
+        dependencies: &[usize],
+        local: bool,
+    ) -> usize {
+        let none = Archetype::default();
+        self.add_system_accessing(system, &none, &none, dependencies, local)
+    }
+
+    /// Like `add_system`, but the system is also treated as reading and writing the data in the
+    /// provided archetypes.
+    pub fn add_system_accessing<S: System + 'static>(
+        &mut self,
+        system: S,
+        read: &Archetype,
+        write: &Archetype,
+        dependencies: &[usize],
+        local: bool,
+    ) -> usize {
+        // Create channels
+        let (thread_sender, finished) = crossbeam_channel::bounded(1);
+
+        let mut write_types = S::Components::write_archetype();
+        let mut all_types = S::Components::archetype();
+        write_types.extend(write);
+        all_types.extend(read);
+        all_types.extend(write);
+
+        // Add the stage
+        let idx = self.systems.insert(SystemStage {
+            system: Box::new(system),
+            name: std::any::type_name::<S>(),
+            write_types,
+            all_types,
+            state: SystemState {
+                extra_read: read.clone(),
+                extra_write: write.clone(),
+                ..SystemState::default()
+            },
+            conditions: Vec::default(),
+            skipped: false,
+            enabled: true,
+            local,
+            dependencies: dependencies.to_vec(),
+            waiting_on: dependencies.len(),
+            dependents: Vec::default(),
+            scheduled: None,
+            cost: None,
+            measured: None,
+            ranked: DEFAULT_COST,
+            critical_path: Duration::ZERO,
+            thread_sender,
+            finished,
+        });
+
+        // Notify all dependencies of the new dependent
+        for dependency in dependencies {
+            self.systems[*dependency].dependents.push(idx);
+        }
+
+        // Determine which systems the new one is compatible with
+        if self.compatibility.len() <= idx {
+            self.compatibility.resize(idx + 1, BitArray::ZERO);
+        }
+
+        let mut compatible: SystemSet = BitArray::ZERO;
+        for (i, other_system) in self.systems.iter() {
+            let system = &self.systems[idx];
+
+            // Write archetypes must not overlap (also, we are compatible with ourselves)
+            if (i != idx)
+                && (system.all_types.any_of(&other_system.write_types)
+                    || other_system.all_types.any_of(&system.write_types))
+            {
+                continue;
+            }
+
+            compatible.set(i, true);
+            self.compatibility[i].set(idx, true);
+        }
+        self.compatibility[idx] = compatible;
+
+        // Results involving a previous system in the same slot are no longer valid
+        self.invalidate(idx);
+        self.order.clear();
+
+        idx
+    }
+
+    /// Removes a system from the schedule. Systems that depended on the removed system inherit
+    /// its dependencies so the ordering between the remaining systems is preserved.
+    pub fn remove_system(&mut self, idx: usize) {
+        let removed = self.systems.remove(idx);
+
+        for dependency in &removed.dependencies {
+            self.systems[*dependency]
+                .dependents
+                .retain(|dependent| *dependent != idx);
+        }
+
+        for dependent in &removed.dependents {
+            self.systems[*dependent]
+                .dependencies
+                .retain(|dependency| *dependency != idx);
+
+            for dependency in &removed.dependencies {
+                if !self.systems[*dependent].dependencies.contains(dependency) {
+                    self.systems[*dependent].dependencies.push(*dependency);
+                    self.systems[*dependency].dependents.push(*dependent);
+                }
+            }
+        }
+
+        // Nothing is compatible with an empty slot
+        for compatible in &mut self.compatibility {
+            compatible.set(idx, false);
+        }
+        self.compatibility[idx] = BitArray::ZERO;
+
+        self.invalidate(idx);
+        self.order.clear();
+    }
+
+    /// Enables or disables a system. Disabled systems never run, but still count as finished so
+    /// their dependents are released.
+    #[inline]
+    pub fn set_enabled(&mut self, idx: usize, enabled: bool) {
+        self.systems[idx].enabled = enabled;
+    }
+
+    /// Adds a run condition to a system.
+    #[inline]
+    pub fn add_condition(&mut self, idx: usize, condition: Box<dyn RunCondition>) {
+        self.systems[idx].conditions.push(condition);
+    }
+
+    /// Declares how long a system takes to run. Used to find the critical path instead of the
+    /// measured run time.
+    #[inline]
+    pub fn set_cost(&mut self, idx: usize, cost: Duration) {
+        self.systems[idx].cost = Some(cost);
+    }
+
+    /// Enables or disables preferring systems on the critical path when picking which systems
+    /// run in parallel.
+    pub fn set_critical_path(&mut self, critical_path: bool) {
+        if self.critical_path != critical_path {
+            self.critical_path = critical_path;
+            self.priority.clear();
+            self.cache.clear();
+        }
+    }
+
+    /// Recomputes the critical path length of every system. Cached results were picked using
+    /// the old lengths, so the cache is cleared if any of them changed.
+    fn update_critical_paths(&mut self) {
+        if self.order.len() != self.systems.len() {
+            self.order = topological_order(&self.systems);
+            self.priority.clear();
+        }
+
+        let mut changed = false;
+
+        // Walk backwards so every dependent is visited before its dependencies
+        for &idx in self.order.iter().rev() {
+            let longest = self.systems[idx]
+                .dependents
+                .iter()
+                .map(|dependent| self.systems[*dependent].critical_path)
+                .max()
+                .unwrap_or(Duration::ZERO);
+
+            let system = &mut self.systems[idx];
+            let cost = if !system.enabled {
+                Duration::ZERO
+            } else if let Some(cost) = system.cost {
+                cost
+            } else {
+                let measured = system.measured.unwrap_or(DEFAULT_COST);
+                if system.ranked.abs_diff(measured) * 100
+                    > system.ranked.max(measured) * RERANK_PERCENT
+                {
+                    system.ranked = measured;
+                }
+                system.ranked
+            };
+            changed |= system.critical_path != cost + longest;
+            system.critical_path = cost + longest;
+        }
+
+        if changed || self.priority.is_empty() {
+            self.priority = self.order.clone();
+            self.priority
+                .sort_by_key(|idx| Reverse(self.systems[*idx].critical_path));
+            self.cache.clear();
+        }
+    }
+
+    /// Removes every cached result involving the system at the provided index.
+    fn invalidate(&mut self, idx: usize) {
+        self.cache
+            .retain(|(running, pending), _| !running[idx] && !pending[idx]);
+    }
+
+    /// Runs one tick of every system within the schedule using a given world. When a frame
+    /// profile is provided, the timings of the systems and the scheduler are recorded into it.
+    pub fn run(
+        &mut self,
+        world: &World,
+        thread_pool: &ThreadPool,
+        mut profile: Option<&mut FrameProfile>,
+    ) {
+        // Systems must be timed to measure their cost
+        let timed = profile.is_some() || self.critical_path;
+        if self.critical_path {
+            self.update_critical_paths();
+        }
+
+        let ready = &mut self.cached_buffers.ready;
+        let pending = &mut self.cached_buffers.pending;
+        let finished = &mut self.cached_buffers.finished;
+        let running = &mut self.cached_buffers.running;
+        let local = &mut self.cached_buffers.local;
+
+        ready.clear();
+        pending.clear();
+        finished.clear();
+        running.clear();
+        local.clear();
+
+        // Setup: reset waiting counters and evaluate run conditions. Conditions are checked
+        // before any system runs so they can safely look at the world. Systems with no
+        // dependencies are ready.
+        for (i, system) in self.systems.iter_mut() {
+            system.waiting_on = system.dependencies.len();
+            system.skipped = !system.enabled
+                || !system
+                    .conditions
+                    .iter_mut()
+                    .all(|condition| condition.should_run(world));
+
+            if system.dependencies.is_empty() {
+                ready.push(i);
+            }
+        }
+
+        make_pending(&mut self.systems, ready, pending, finished);
+
+        // Loop until all systems have finished
+        while finished.len() != self.systems.len() {
+            // Run a single main thread system before polling for completions. It notifies us of
+            // completion the same way a system on a worker thread would.
+            if let Some(idx) = local.pop() {
+                let system = &mut self.systems[idx];
+                let started = timed.then(Instant::now);
+                system.system.generic_tick(world, &system.state);
+                let timing = started.map(|started| (started, Instant::now(), None));
+                system.thread_sender.send(timing).unwrap();
+            }
+
+            // Determine systems that have finished running
+            let to_remove = &mut self.cached_buffers.to_remove;
+            to_remove.clear();
+
+            for system in running.iter() {
+                let idx = *system;
+
+                // Check to see if the system has finished running
+                let timing = match self.systems[idx].finished.try_recv() {
+                    Ok(timing) => timing,
+                    Err(_) => continue,
+                };
+
+                to_remove.push(idx);
+                finished.push(idx);
+
+                if let (true, Some((started, ended, _))) = (self.critical_path, timing) {
+                    let system = &mut self.systems[idx];
+                    let duration = ended.duration_since(started);
+                    system.measured = Some(match system.measured {
+                        Some(measured) => (measured * 3 + duration) / 4,
+                        None => duration,
+                    });
+                }
+
+                if let (Some(profile), Some((started, ended, thread))) =
+                    (profile.as_deref_mut(), timing)
+                {
+                    let system = &self.systems[idx];
+                    profile.systems.push(SystemTiming {
+                        system: SystemId {
+                            stage: self.stage,
+                            idx,
+                        },
+                        name: system.name,
+                        scheduled: system.scheduled.unwrap_or(started),
+                        started,
+                        finished: ended,
+                        thread,
+                    });
+                }
+
+                // Notify dependencies of the completion
+                // NOTE: Borrow checker bullsh*t means we can't iterate over `dependents` while
+                // modifying `systems` because of mutable/immutable borrow.
+                for i in 0..self.systems[idx].dependents.len() {
+                    let dependent_idx = self.systems[idx].dependents[i];
+                    let dependent = &mut self.systems[dependent_idx];
+
+                    dependent.waiting_on -= 1;
+
+                    // Move to pending if we aren't waiting anymore
+                    if dependent.waiting_on == 0 {
+                        ready.push(dependent_idx);
+                    }
+                }
+            }
+
+            for idx in to_remove {
+                running.remove(idx);
+            }
+
+            make_pending(&mut self.systems, ready, pending, finished);
+
+            // If there are no new pending systems, we loop
+            if pending.is_empty() {
+                continue;
+            }
+
+            // Create system sets
+            let mut running_set: SystemSet = BitArray::ZERO;
+            let mut pending_set: SystemSet = BitArray::ZERO;
+
+            for idx in running.iter() {
+                running_set.set(*idx, true);
+            }
+
+            for idx in pending.iter() {
+                pending_set.set(*idx, true);
+            }
+
+            // Check if we've seen this combo already in the cache. The split between running and
+            // pending systems matters, since only pending systems can be started.
+            let key = (running_set, pending_set);
+
+            let lookup_start = profile.as_ref().map(|_| Instant::now());
+            let cached = self.cache.get(&key);
+            if let (Some(profile), Some(lookup_start)) = (profile.as_deref_mut(), lookup_start) {
+                profile.scheduler.cache_lookup += lookup_start.elapsed();
+                if cached.is_some() {
+                    profile.scheduler.cache_hits += 1;
+                } else {
+                    profile.scheduler.cache_misses += 1;
+                }
+            }
+
+            let to_run = if let Some(result) = cached {
+                result
+            }
+            // Not in the cache. Need to perform Bron-Kerbosch
+            else {
+                let search_start = profile.as_ref().map(|_| Instant::now());
+
+                let max_cliques = &mut self.cached_buffers.bron_kerbosch;
+                max_cliques.clear();
+
+                // Every clique contains the running systems, so only pending systems compatible
+                // with all of them are candidates
+                let mut candidates = pending_set;
+                for idx in running_set.iter_ones() {
+                    candidates = candidates.bitand(self.compatibility[idx]);
+                }
+
+                bron_kerbosch(
+                    running_set,
+                    candidates,
+                    BitArray::ZERO,
+                    &self.compatibility,
+                    max_cliques,
+                );
+
+                // Pick the best amongst all the maximal cliques. Normally that is the largest one.
+                // When preferring the critical path, it is the one whose systems have the longest
+                // critical paths combined, so long chains of dependencies aren't starved.
+                let systems = &self.systems;
+                let critical_path = self.critical_path;
+                let score = |clique: &SystemSet| {
+                    let length = if critical_path {
+                        clique
+                            .iter_ones()
+                            .map(|idx| systems[idx].critical_path)
+                            .sum()
+                    } else {
+                        Duration::ZERO
+                    };
+                    (length, clique.count_ones())
+                };
+
+                let mut result = if max_cliques.is_empty() {
+                    BitArray::ZERO
+                } else {
+                    // Find the maximum amongst all the cliques
+                    let mut max = 0;
+                    let mut max_score = score(&max_cliques[0]);
+
+                    for (i, clique) in max_cliques.iter().enumerate().skip(1) {
+                        let clique_score = score(clique);
+                        if clique_score > max_score {
+                            max = i;
+                            max_score = clique_score;
+                        }
+                    }
+
+                    max_cliques[max]
+                };
+
+                // Get rid of the running systems
+                result = result.bitxor(running_set);
+                let mut to_cache = Vec::with_capacity(result.count_ones());
+                for i in result.iter_ones() {
+                    to_cache.push(i);
+                }
+
+                if let (Some(profile), Some(search_start)) = (profile.as_deref_mut(), search_start)
+                {
+                    profile.scheduler.bron_kerbosch += search_start.elapsed();
+                }
+
+                // Add to the cache
+                self.cache.insert(key, to_cache);
+                self.cache.get(&key).unwrap()
+            };
+
+            // Send all compatible systems to the thread pool
+            for system in to_run {
+                let idx = *system;
+
+                // Ignore if already running
+                if running.contains(&idx) {
+                    continue;
+                }
+
+                running.insert(idx);
+                pending.remove(&idx);
+
+                if profile.is_some() {
+                    self.systems[idx].scheduled = Some(Instant::now());
+                }
+
+                // Main thread systems are run in between polling
+                if self.systems[idx].local {
+                    local.push(idx);
+                    continue;
+                }
+
+                let packet = SystemPacket {
+                    system: unsafe {
+                        NonNull::new_unchecked(self.systems[idx].system.as_mut() as *mut _)
+                    },
+                    state: (&self.systems[idx].state) as *const _,
+                    world: world as *const _,
+                    thread_sender: self.systems[idx].thread_sender.clone(),
+                    timed,
+                };
+
+                thread_pool.spawn(move || unsafe {
+                    // Move packet to the thread
+                    let mut packet = packet;
+
+                    // Convert back to references
+                    let world = packet.world.as_ref().unwrap();
+                    let state = packet.state.as_ref().unwrap();
+
+                    // Run the system
+                    let started = packet.timed.then(Instant::now);
+                    packet.system.as_mut().generic_tick(world, state);
+                    let timing = started
+                        .map(|started| (started, Instant::now(), rayon::current_thread_index()));
+
+                    // Notify the main thread that the system has completed
+                    packet.thread_sender.send(timing).unwrap();
+                });
+            }
+        }
+    }
+}
+
+impl SystemSlots {
+    /// Number of systems in the container.
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Puts a system in an empty slot and returns the index of the slot.
+    fn insert(&mut self, system: SystemStage) -> usize {
+        self.len += 1;
+
+        if let Some(idx) = self.free.pop() {
+            self.slots[idx] = Some(system);
+            idx
+        } else {
+            assert_ne!(self.slots.len(), MAX_SYSTEMS);
+            self.slots.push(Some(system));
+            self.slots.len() - 1
+        }
+    }
+
+    /// Takes the system out of a slot.
+    fn remove(&mut self, idx: usize) -> SystemStage {
+        let system = self.slots[idx].take().expect("Removed non existant system");
+        self.free.push(idx);
+        self.len -= 1;
+        system
+    }
+
+    /// Returns an iterator over every system and its index.
+    fn iter(&self) -> impl Iterator<Item = (usize, &SystemStage)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, slot)| slot.as_ref().map(|system| (i, system)))
+    }
+
+    /// Returns an iterator over every system and its index.
+    fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut SystemStage)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, slot)| slot.as_mut().map(|system| (i, system)))
+    }
+}
+
+impl Index<usize> for SystemSlots {
+    type Output = SystemStage;
+
+    #[inline]
+    fn index(&self, idx: usize) -> &Self::Output {
+        self.slots[idx]
+            .as_ref()
+            .expect("Accessed non existant system")
+    }
+}
+
+impl IndexMut<usize> for SystemSlots {
+    #[inline]
+    fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
+        self.slots[idx]
+            .as_mut()
+            .expect("Accessed non existant system")
+    }
+}
+
+impl Schedule {
+    /// Runs one tick of every system within the schedule on the calling thread. Systems run in a
+    /// topological order of the dependency graph where ties are broken by the index of the
+    /// system, so the order is the same on every run and every machine.
+    pub fn run_deterministic(&mut self, world: &World, mut profile: Option<&mut FrameProfile>) {
+        if self.order.len() != self.systems.len() {
+            self.order = topological_order(&self.systems);
+        }
+
+        // Conditions are evaluated up front, just like when running in parallel
+        for (_, system) in self.systems.iter_mut() {
+            system.skipped = !system.enabled
+                || !system
+                    .conditions
+                    .iter_mut()
+                    .all(|condition| condition.should_run(world));
+        }
+
+        for &idx in &self.order {
+            let system = &mut self.systems[idx];
+            if system.skipped {
+                continue;
+            }
+
+            let started = Instant::now();
+            system.system.generic_tick(world, &system.state);
+
+            if let Some(profile) = profile.as_deref_mut() {
+                profile.systems.push(SystemTiming {
+                    system: SystemId {
+                        stage: self.stage,
+                        idx,
+                    },
+                    name: system.name,
+                    scheduled: started,
+                    started,
+                    finished: Instant::now(),
+                    thread: None,
+                });
+            }
+        }
+    }
+}
+
+/// Helper function that sorts systems topologically, always picking the system with the lowest
+/// index amongst those whose dependencies are satisfied.
+fn topological_order(systems: &SystemSlots) -> Vec<usize> {
+    let mut order = Vec::with_capacity(systems.len());
+    let mut waiting_on = vec![0; MAX_SYSTEMS];
+    let mut available = BinaryHeap::default();
+
+    for (i, system) in systems.iter() {
+        waiting_on[i] = system.dependencies.len();
+        if waiting_on[i] == 0 {
+            available.push(Reverse(i));
+        }
+    }
+
+    while let Some(Reverse(idx)) = available.pop() {
+        order.push(idx);
+
+        for dependent in &systems[idx].dependents {
+            waiting_on[*dependent] -= 1;
+            if waiting_on[*dependent] == 0 {
+                available.push(Reverse(*dependent));
+            }
+        }
+    }
+
+    order
+}
+
+/// Helper function that moves ready systems into the pending set. Systems skipped by their run
+/// conditions are immediately marked as finished, which may make their dependents ready.
+fn make_pending(
+    systems: &mut SystemSlots,
+    ready: &mut Vec<usize>,
+    pending: &mut HashSet<usize>,
+    finished: &mut Vec<usize>,
+) {
+    while let Some(idx) = ready.pop() {
+        if !systems[idx].skipped {
+            pending.insert(idx);
+            continue;
+        }
+
+        finished.push(idx);
+
+        for i in 0..systems[idx].dependents.len() {
+            let dependent_idx = systems[idx].dependents[i];
+            let dependent = &mut systems[dependent_idx];
+
+            dependent.waiting_on -= 1;
+
+            if dependent.waiting_on == 0 {
+                ready.push(dependent_idx);
+            }
+        }
+    }
+}
+
+/// Helper function that performs the Bron-Kerbosch algorithm.
+fn bron_kerbosch(
+    r: SystemSet,
+    mut p: SystemSet,
+    mut x: SystemSet,
+    compatibility: &[SystemSet],
+    out: &mut Vec<SystemSet>,
+) {
+    if p.not_any() && x.not_any() {
+        out.push(r);
+        return;
+    }
+
+    let px = p.bitor(x);
+    let pivot = px.first_one().unwrap();
+
+    let mut nh_pivot = compatibility[pivot];
+    nh_pivot.set(pivot, false);
+
+    let p_removing_nh_pivot = p & (nh_pivot.not());
+
+    for v in p_removing_nh_pivot.iter_ones() {
+        let mut nh_v = compatibility[v];
+        nh_v.set(v, false);
+
+        let mut new_r = r;
+        new_r.set(v, true);
+
+        let new_p = p.bitand(nh_v);
+
+        let new_x = x.bitand(nh_v);
+
+        bron_kerbosch(new_r, new_p, new_x, compatibility, out);
+
+        p.set(v, false);
+        x.set(v, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Schedule;
+    use crate::dispatcher::Stage;
+    use crate::{
+        component::{filter::Write, Component},
+        system::{query::QueryGenerator, System, SystemAccess},
+        world::World,
+    };
+    use rayon::ThreadPoolBuilder;
+    use std::{
+        marker::PhantomData,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    struct Shared(#[allow(dead_code)] u32);
+
+    impl Component for Shared {}
+
+    struct Writer;
+
+    impl System for Writer {
+        type Components = (Write<Shared>,);
+
+        fn tick(&mut self, _: QueryGenerator) {}
+    }
+
+    #[test]
+    fn removal_keeps_unrelated_cache_entries() {
+        let world = World::new();
+        let thread_pool = ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+
+        let mut schedule = Schedule::new(Stage::Update);
+        schedule.add_system(Writer, &[], false);
+        let second = schedule.add_system(Writer, &[], false);
+        let third = schedule.add_system(Writer, &[second], false);
+
+        // Every system writes the same component, so none of them are compatible
+        for idx in [0, second, third] {
+            assert_eq!(schedule.compatibility[idx].count_ones(), 1);
+        }
+
+        schedule.run(&world, &thread_pool, None);
+        let first_pass = schedule.cache.len();
+        assert!(schedule
+            .cache
+            .keys()
+            .any(|(running, pending)| !running[third] && !pending[third]));
+
+        schedule.remove_system(third);
+        assert!(schedule.cache.len() < first_pass);
+        assert!(!schedule.cache.is_empty());
+        assert!(schedule
+            .cache
+            .keys()
+            .all(|(running, pending)| !running[third] && !pending[third]));
+
+        schedule.run(&world, &thread_pool, None);
+    }
+
+    struct Left(#[allow(dead_code)] u32);
+    struct Right(#[allow(dead_code)] u32);
+
+    impl Component for Left {}
+    impl Component for Right {}
+
+    struct Logged<C>(&'static str, Arc<Mutex<Vec<&'static str>>>, PhantomData<C>);
+
+    impl<C: SystemAccess> System for Logged<C> {
+        type Components = C;
+
+        fn tick(&mut self, _: QueryGenerator) {
+            self.1.lock().unwrap().push(self.0);
+        }
+    }
+
+    fn first_to_run(critical_path: bool) -> &'static str {
+        let world = World::new();
+        let thread_pool = ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        let log = Arc::new(Mutex::new(Vec::default()));
+
+        // A chain that conflicts with two leaves that are compatible with each other
+        let mut schedule = Schedule::new(Stage::Update);
+        schedule.set_critical_path(critical_path);
+        let mut chain = Vec::default();
+        for name in ["chain 1", "chain 2", "chain 3"] {
+            let dependencies = chain.last().map(|idx| vec![*idx]).unwrap_or_default();
+            chain.push(schedule.add_system(
+                Logged::<(Write<Left>, Write<Right>)>(name, log.clone(), PhantomData),
+                &dependencies,
+                false,
+            ));
+        }
+        let left = schedule.add_system(
+            Logged::<(Write<Left>,)>("left", log.clone(), PhantomData),
+            &[],
+            false,
+        );
+        let right = schedule.add_system(
+            Logged::<(Write<Right>,)>("right", log.clone(), PhantomData),
+            &[],
+            false,
+        );
+
+        for idx in chain.into_iter().chain([left, right]) {
+            schedule.set_cost(idx, Duration::from_millis(1));
+        }
+
+        schedule.run(&world, &thread_pool, None);
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 5);
+        log[0]
+    }
+
+    #[test]
+    fn critical_path_runs_first() {
+        assert_eq!(first_to_run(true), "chain 1");
+        assert_ne!(first_to_run(false), "chain 1");
+    }
+
+    #[test]
+    fn jitter_keeps_priorities() {
+        let mut schedule = Schedule::new(Stage::Update);
+        schedule.set_critical_path(true);
+        let slow = schedule.add_system(Writer, &[], false);
+        let fast = schedule.add_system(Writer, &[], false);
+
+        let mut measure = |slow_cost: u64, fast_cost: u64| {
+            schedule.systems[slow].measured = Some(Duration::from_micros(slow_cost));
+            schedule.systems[fast].measured = Some(Duration::from_micros(fast_cost));
+            schedule.update_critical_paths();
+            schedule.priority.clone()
+        };
+
+        assert_eq!(measure(100, 90), vec![slow, fast]);
+        // Small changes don't reorder the systems, even if the measurements swap places
+        assert_eq!(measure(95, 105), vec![slow, fast]);
+        assert_eq!(measure(50, 105), vec![fast, slow]);
+    }
+
+    #[test]
+    fn critical_path_changes_clear_cache() {
+        let world = World::new();
+        let thread_pool = ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+
+        let mut schedule = Schedule::new(Stage::Update);
+        schedule.set_critical_path(true);
+        let slow = schedule.add_system(Writer, &[], false);
+        let fast = schedule.add_system(Writer, &[], false);
+        schedule.set_cost(slow, Duration::from_micros(100));
+        schedule.set_cost(fast, Duration::from_micros(50));
+
+        schedule.run(&world, &thread_pool, None);
+        assert!(!schedule.cache.is_empty());
+
+        // The order stays the same, but cliques were scored with the old lengths
+        schedule.set_cost(slow, Duration::from_micros(200));
+        schedule.update_critical_paths();
+        assert_eq!(schedule.priority, vec![slow, fast]);
+        assert!(schedule.cache.is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+};
+
+use crate::prw_lock::{PrwLock, PrwReadHandle, PrwWriteHandle};
+
+/// An event is a message that systems send to one another.
+pub trait Event: Send + Sync {}
+
+/// A double buffered queue of events of a single type.
+///
+/// Events live for two runs of the dispatcher. This way, every reader gets a chance to see an
+/// event regardless of whether the reader runs before or after the writer that sent it.
+pub struct Events<E: Event> {
+    /// Events sent during the previous run of the dispatcher.
+    previous: Vec<E>,
+    /// Events sent during the current run of the dispatcher.
+    current: Vec<E>,
+    /// Total number of events sent before the first event in `previous`.
+    offset: usize,
+}
+
+/// Holds the event queues for every event type registered in a world.
+#[derive(Default)]
+pub struct EventChannels {
+    /// Maps the type ID of an event to the lock holding its `Events` queue.
+    channels: HashMap<TypeId, Box<dyn GenericEvents>>,
+}
+
+trait GenericEvents: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+
+    /// Swaps the event buffers, dropping the events sent two runs ago.
+    fn update(&mut self);
+}
+
+/// Read access to the events of a single type.
+///
+/// Also used within `System::Components` to declare that a system reads events of type `E`.
+pub struct EventReader<E: Event> {
+    handle: PrwReadHandle<Events<E>>,
+    /// Number of events sent before the first event this reader yields.
+    cursor: usize,
+}
+
+/// Write access to the events of a single type.
+///
+/// Also used within `System::Components` to declare that a system sends events of type `E`.
+pub struct EventWriter<E: Event> {
+    handle: PrwWriteHandle<Events<E>>,
+}
+
+impl<E: Event> Default for Events<E> {
+    fn default() -> Self {
+        Self {
+            previous: Vec::default(),
+            current: Vec::default(),
+            offset: 0,
+        }
+    }
+}
+
+impl<E: Event> Events<E> {
+    #[inline]
+    pub fn send(&mut self, event: E) {
+        self.current.push(event);
+    }
+
+    /// Number of events currently buffered.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.previous.len() + self.current.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total number of events that have ever been sent.
+    #[inline]
+    pub(crate) fn end(&self) -> usize {
+        self.offset + self.len()
+    }
+
+    /// Swaps the event buffers. Events in `previous` are dropped and events in `current` become
+    /// the new `previous`.
+    pub(crate) fn update(&mut self) {
+        self.offset += self.previous.len();
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.current.clear();
+    }
+
+    /// Returns an iterator over every buffered event that was sent after the first `cursor`
+    /// events.
+    pub(crate) fn iter_from(&self, cursor: usize) -> impl Iterator<Item = &E> {
+        let skip = cursor.saturating_sub(self.offset);
+        self.previous.iter().chain(self.current.iter()).skip(skip)
+    }
+}
+
+impl EventChannels {
+    /// Creates the event queue for an event type. Should do nothing if the queue already exists.
+    pub fn register<E: Event + 'static>(&mut self) {
+        self.channels
+            .entry(TypeId::of::<Events<E>>())
+            .or_insert_with(|| Box::new(PrwLock::new(Events::<E>::default())));
+    }
+
+    /// Get the event queue for an event type.
+    ///
+    /// Returns `None` if the event type was never registered.
+    pub fn get<E: Event + 'static>(&self) -> Option<&PrwLock<Events<E>>> {
+        self.channels
+            .get(&TypeId::of::<Events<E>>())
+            .and_then(|channel| channel.as_any().downcast_ref::<PrwLock<Events<E>>>())
+    }
+
+    /// Swaps the buffers of every event queue. Called by the dispatcher once per run.
+    pub(crate) fn update(&mut self) {
+        for channel in self.channels.values_mut() {
+            channel.update();
+        }
+    }
+}
+
+impl<E: Event + 'static> GenericEvents for PrwLock<Events<E>> {
+    #[inline]
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    #[inline]
+    fn update(&mut self) {
+        self.write().update();
+    }
+}
+
+impl<E: Event> EventReader<E> {
+    #[inline]
+    pub(crate) fn new(handle: PrwReadHandle<Events<E>>, cursor: usize) -> Self {
+        Self { handle, cursor }
+    }
+
+    /// Returns an iterator over every event the system has not seen yet.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &E> {
+        self.handle.iter_from(self.cursor)
+    }
+
+    /// Number of events the system has not seen yet.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.handle.end() - self.cursor.max(self.handle.offset)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<E: Event> EventWriter<E> {
+    #[inline]
+    pub(crate) fn new(handle: PrwWriteHandle<Events<E>>) -> Self {
+        Self { handle }
+    }
+
+    #[inline]
+    pub fn send(&mut self, event: E) {
+        self.handle.send(event);
+    }
+
+    pub fn send_batch(&mut self, events: impl IntoIterator<Item = E>) {
+        self.handle.current.extend(events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, Events};
+    use crate::{
+        dispatcher::Dispatcher,
+        event::{EventReader, EventWriter},
+        system::{query::QueryGenerator, System},
+        world::World,
+    };
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct Ping(u32);
+
+    impl Event for Ping {}
+
+    #[test]
+    fn events_double_buffering() {
+        let mut events = Events::default();
+        events.send(Ping(1));
+        events.send(Ping(2));
+        assert_eq!(events.iter_from(0).count(), 2);
+
+        events.update();
+        events.send(Ping(3));
+        assert_eq!(events.iter_from(0).count(), 3);
+        assert_eq!(events.iter_from(2).collect::<Vec<_>>(), vec![&Ping(3)]);
+
+        // The first two events are dropped after the second update
+        events.update();
+        assert_eq!(events.iter_from(0).collect::<Vec<_>>(), vec![&Ping(3)]);
+        assert_eq!(events.iter_from(3).count(), 0);
+        assert_eq!(events.end(), 3);
+    }
+
+    struct Sender;
+
+    impl System for Sender {
+        type Components = (EventWriter<Ping>,);
+
+        fn tick(&mut self, gen: QueryGenerator) {
+            let mut writer = gen.event_writer::<Ping>();
+            writer.send(Ping(1));
+            writer.send(Ping(2));
+        }
+    }
+
+    struct Receiver(Arc<AtomicUsize>);
+
+    impl System for Receiver {
+        type Components = (EventReader<Ping>,);
+
+        fn tick(&mut self, gen: QueryGenerator) {
+            let reader = gen.event_reader::<Ping>();
+            assert_eq!(reader.len(), reader.iter().count());
+            self.0.fetch_add(reader.len(), Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn events_between_systems() {
+        let mut world = World::new();
+        world.add_events::<Ping>();
+
+        let before = Arc::new(AtomicUsize::new(0));
+        let after = Arc::new(AtomicUsize::new(0));
+
+        // One reader runs before the sender and one after it. Both must see every event exactly
+        // once.
+        let mut dispatcher = Dispatcher::builder();
+        let first = dispatcher.with_system(Receiver(before.clone()), &[]);
+        let sender = dispatcher.with_system(Sender, &[first]);
+        dispatcher.with_system(Receiver(after.clone()), &[sender]);
+        let mut dispatcher = dispatcher.build();
+
+        dispatcher.run(&mut world);
+        assert_eq!(before.load(Ordering::Relaxed), 0);
+        assert_eq!(after.load(Ordering::Relaxed), 2);
+
+        world.send_event(Ping(3));
+
+        dispatcher.run(&mut world);
+        assert_eq!(before.load(Ordering::Relaxed), 3);
+        assert_eq!(after.load(Ordering::Relaxed), 5);
+    }
+}
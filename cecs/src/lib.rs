@@ -1,8 +1,24 @@
+// The original tests predate running clippy on test targets
+#![cfg_attr(
+    test,
+    allow(
+        clippy::bool_assert_comparison,
+        clippy::unnecessary_cast,
+        clippy::useless_conversion
+    )
+)]
+
+// Lets the derive macros refer to the crate as `::cecs` from within it too
+extern crate self as cecs;
+
 pub mod archetype;
 pub mod component;
 pub mod dispatcher;
 pub mod entity;
+pub mod event;
 pub mod prw_lock;
+#[cfg(test)]
+mod rng;
 pub mod system;
 pub mod world;
 
@@ -10,9 +26,9 @@ pub mod world;
 mod tests {
     use crate::archetype::Archetype;
     use crate::component::filter::{Read, Write};
+    use crate::component::registry::ComponentRegistry;
     use crate::component::Component;
     use crate::{dispatcher::Dispatcher, system::System, world::World};
-    use std::any::TypeId;
 
     struct ComponentA(u32);
     struct ComponentB(u32);
@@ -117,13 +133,15 @@ mod tests {
     fn check_set_comparisons() {
         let mut one = Archetype::default();
         let mut two = Archetype::default();
-        one.add_component_by_id(TypeId::of::<u32>());
-        two.add_component_by_id(TypeId::of::<u32>());
+        one.add_component_by_id(ComponentRegistry::id::<u32>());
+        two.add_component_by_id(ComponentRegistry::id::<u32>());
         assert_eq!(one.subset_of(&two), true);
 
-        one.add_component_by_id(TypeId::of::<i64>());
+        one.add_component_by_id(ComponentRegistry::id::<i64>());
         assert_eq!(one.subset_of(&two), false);
         assert_eq!(two.subset_of(&one), true);
+        assert!(one.any_of(&two));
+        assert_eq!(one.iter().collect::<Vec<_>>().len(), one.len());
 
         assert_eq!(1, 1);
     }
@@ -1,13 +1,36 @@
 use std::{
+    cell::UnsafeCell,
+    error::Error,
+    fmt,
     ops::{Deref, DerefMut},
+};
+
+// The loom model checker needs its own versions of everything used to synchronize threads
+#[cfg(loom)]
+use loom::{
+    hint,
     sync::{
         atomic::{AtomicU32, Ordering},
         Arc,
     },
+    thread,
+};
+#[cfg(not(loom))]
+use std::{
+    hint,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    thread,
 };
 
 /// A panicky read-write lock.
 ///
+/// `read` and `write` panic on contention, since the dispatcher guarantees there is none. Code
+/// running outside of the dispatcher can use `try_read`/`try_write` or the blocking variants
+/// instead.
+///
 /// There is a problem with the default implementation of the default Rust read-write lock:
 /// https://doc.rust-lang.org/std/sync/struct.RwLock.html
 ///
@@ -54,62 +77,220 @@ use std::{
 /// The tricky part of the implementation is going to be getting the read-write logic down. We need
 /// to allow multiple threads to request reads and writes. There can be as many readers as we want,
 /// but once someone requests write access, we must ensure that no one else is reading or writing.
+///
+/// Acquiring a handle is an `Acquire` operation and dropping one is a `Release` operation, so
+/// everything a writer did is visible to whoever gets access after it.
 pub struct PrwLock<T>(Arc<PrwLockInner<T>>);
 
+/// Value of `access_state` while there is a writer. Otherwise, `access_state` is the number of
+/// readers.
+const WRITER: u32 = u32::MAX;
+
+/// Number of times blocking acquires spin before yielding the thread.
+const SPINS: u32 = 64;
+
 struct PrwLockInner<T> {
-    data: T,
+    data: UnsafeCell<T>,
     access_state: AtomicU32,
+    /// Who currently holds handles to the lock. Only tracked in debug builds.
+    #[cfg(all(debug_assertions, not(loom)))]
+    holders: std::sync::Mutex<Vec<Holder>>,
 }
 
-pub struct PrwReadHandle<T>(Arc<PrwLockInner<T>>);
+pub struct PrwReadHandle<T> {
+    lock: Arc<PrwLockInner<T>>,
+    #[cfg(all(debug_assertions, not(loom)))]
+    holder: Holder,
+}
 
-pub struct PrwWriteHandle<T>(Arc<PrwLockInner<T>>);
+pub struct PrwWriteHandle<T> {
+    lock: Arc<PrwLockInner<T>>,
+    #[cfg(all(debug_assertions, not(loom)))]
+    holder: Holder,
+}
+
+/// Describes a handle to a lock for diagnostics. Only recorded in debug builds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Holder {
+    /// Name of the system that acquired the handle. `None` if it was acquired outside of a
+    /// system.
+    pub system: Option<&'static str>,
+    pub write: bool,
+}
+
+/// Returned when access to a `PrwLock` can't be acquired without waiting for another handle to
+/// be dropped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WouldBlock;
 
 impl<T> PrwLock<T> {
     pub fn new(data: T) -> Self {
         Self(Arc::new(PrwLockInner {
-            data,
+            data: UnsafeCell::new(data),
             access_state: AtomicU32::new(0),
+            #[cfg(all(debug_assertions, not(loom)))]
+            holders: std::sync::Mutex::default(),
         }))
     }
 
+    /// Every handle currently held. Always empty in release builds.
+    pub(crate) fn holders(&self) -> Vec<Holder> {
+        #[cfg(all(debug_assertions, not(loom)))]
+        return self.0.holders.lock().unwrap().clone();
+
+        #[cfg(not(all(debug_assertions, not(loom))))]
+        Vec::default()
+    }
+
     /// Gets read access to the data in the lock.
+    ///
+    /// Panics if there is a writer.
     pub fn read(&self) -> PrwReadHandle<T> {
-        // See who is accessing the PrwLock
-        let access_state = self.0.access_state.fetch_add(1, Ordering::Relaxed);
+        self.try_read()
+            .expect("Requested read access to a PrwLock with a writer")
+    }
 
-        // Panic if there is a writer
-        assert_ne!(access_state, u32::MAX);
+    /// Gets write access to the data in the lock.
+    ///
+    /// Panics if there are readers or a writer.
+    pub fn write(&self) -> PrwWriteHandle<T> {
+        self.try_write()
+            .expect("Requested write access to a PrwLock with readers or a writer")
+    }
 
-        PrwReadHandle(self.0.clone())
+    /// Gets read access to the data in the lock. Fails without modifying the lock if there is a
+    /// writer.
+    pub fn try_read(&self) -> Result<PrwReadHandle<T>, WouldBlock> {
+        let mut access_state = self.0.access_state.load(Ordering::Relaxed);
+
+        loop {
+            if access_state == WRITER {
+                return Err(WouldBlock);
+            }
+
+            // One less than `WRITER` readers would make the next reader look like a writer
+            assert_ne!(access_state, WRITER - 1, "Too many readers of a PrwLock");
+
+            match self.0.access_state.compare_exchange_weak(
+                access_state,
+                access_state + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    return Ok(PrwReadHandle {
+                        lock: self.0.clone(),
+                        #[cfg(all(debug_assertions, not(loom)))]
+                        holder: self.0.hold(false),
+                    })
+                }
+                Err(current) => access_state = current,
+            }
+        }
     }
 
-    /// Gets read access to the data in the lock.
-    pub fn write(&self) -> PrwWriteHandle<T> {
-        // See who is accessing the PrwLock
-        let access_state = self.0.access_state.fetch_add(u32::MAX, Ordering::Relaxed);
+    /// Gets write access to the data in the lock. Fails without modifying the lock if there are
+    /// readers or a writer.
+    pub fn try_write(&self) -> Result<PrwWriteHandle<T>, WouldBlock> {
+        match self
+            .0
+            .access_state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+        {
+            Ok(_) => Ok(PrwWriteHandle {
+                lock: self.0.clone(),
+                #[cfg(all(debug_assertions, not(loom)))]
+                holder: self.0.hold(true),
+            }),
+            Err(_) => Err(WouldBlock),
+        }
+    }
+
+    /// Gets read access to the data in the lock, waiting for the writer to finish if there is
+    /// one. Spins briefly before yielding the thread between attempts.
+    pub fn read_blocking(&self) -> PrwReadHandle<T> {
+        block_on(|| self.try_read())
+    }
 
-        // Panic if there are readers or writers
-        assert_eq!(access_state, 0);
+    /// Gets write access to the data in the lock, waiting for every reader and writer to finish.
+    /// Spins briefly before yielding the thread between attempts.
+    ///
+    /// The lock isn't fair, so a steady stream of readers can keep a writer waiting forever.
+    pub fn write_blocking(&self) -> PrwWriteHandle<T> {
+        block_on(|| self.try_write())
+    }
+}
+
+#[cfg(all(debug_assertions, not(loom)))]
+impl<T> PrwLockInner<T> {
+    /// Records a newly acquired handle.
+    #[inline]
+    fn hold(&self, write: bool) -> Holder {
+        let holder = Holder {
+            system: crate::system::current_system(),
+            write,
+        };
+        self.holders.lock().unwrap().push(holder);
+        holder
+    }
 
-        PrwWriteHandle(self.0.clone())
+    /// Forgets a handle that is about to be released.
+    #[inline]
+    fn release(&self, holder: Holder) {
+        let mut holders = self.holders.lock().unwrap();
+        if let Some(i) = holders.iter().position(|held| *held == holder) {
+            holders.swap_remove(i);
+        }
     }
 }
 
+/// Helper function that retries an acquire until it succeeds.
+fn block_on<H>(mut acquire: impl FnMut() -> Result<H, WouldBlock>) -> H {
+    let mut attempts = 0;
+
+    loop {
+        if let Ok(handle) = acquire() {
+            return handle;
+        }
+
+        if attempts < SPINS {
+            attempts += 1;
+            hint::spin_loop();
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+impl fmt::Display for WouldBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PrwLock is held by a conflicting handle")
+    }
+}
+
+impl Error for WouldBlock {}
+
 impl<T> Deref for PrwReadHandle<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        &self.0.data
+        unsafe { &*self.lock.data.get() }
     }
 }
 
 impl<T> Drop for PrwReadHandle<T> {
     fn drop(&mut self) {
-        self.0.access_state.fetch_sub(1, Ordering::Relaxed);
+        #[cfg(all(debug_assertions, not(loom)))]
+        self.lock.release(self.holder);
+        self.lock.access_state.fetch_sub(1, Ordering::Release);
     }
 }
 
+// Access to the data is synchronized by `access_state`
+unsafe impl<T: Send + Sync> Send for PrwLockInner<T> {}
+
+unsafe impl<T: Send + Sync> Sync for PrwLockInner<T> {}
+
 unsafe impl<T> Send for PrwReadHandle<T> {}
 
 unsafe impl<T> Sync for PrwReadHandle<T> {}
@@ -118,19 +299,21 @@ impl<T> Deref for PrwWriteHandle<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        &self.0.data
+        unsafe { &*self.lock.data.get() }
     }
 }
 
 impl<T> Drop for PrwWriteHandle<T> {
     fn drop(&mut self) {
-        self.0.access_state.store(0, Ordering::Relaxed);
+        #[cfg(all(debug_assertions, not(loom)))]
+        self.lock.release(self.holder);
+        self.lock.access_state.store(0, Ordering::Release);
     }
 }
 
 impl<T> DerefMut for PrwWriteHandle<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { (&self.0.data as *const T as *mut T).as_mut().unwrap() }
+        unsafe { &mut *self.lock.data.get() }
     }
 }
 
@@ -140,7 +323,7 @@ unsafe impl<T> Sync for PrwWriteHandle<T> {}
 
 #[cfg(test)]
 mod tests {
-    use super::PrwLock;
+    use super::{PrwLock, WouldBlock};
 
     #[test]
     fn prw_lock_test() {
@@ -178,4 +361,152 @@ mod tests {
         let mut _handle1 = lock.read();
         let mut _handle2 = lock.write();
     }
+
+    #[test]
+    fn prw_lock_failed_acquires_leave_state_untouched() {
+        let lock = PrwLock::new(42);
+
+        let writer = lock.write();
+        assert_eq!(lock.try_read().err(), Some(WouldBlock));
+        assert_eq!(lock.try_write().err(), Some(WouldBlock));
+        std::mem::drop(writer);
+
+        let reader = lock.try_read().unwrap();
+        assert_eq!(lock.try_write().err(), Some(WouldBlock));
+        assert_eq!(*lock.try_read().unwrap(), 42);
+        std::mem::drop(reader);
+
+        // Only succeeds if every failed attempt was undone
+        let mut writer = lock.try_write().unwrap();
+        *writer += 1;
+        std::mem::drop(writer);
+        assert_eq!(*lock.read(), 43);
+    }
+
+    #[test]
+    fn prw_lock_blocking() {
+        let lock = PrwLock::new(0);
+        let reader = lock.read();
+
+        let writer = std::thread::scope(|scope| {
+            let writer = scope.spawn(|| {
+                let mut writer = lock.write_blocking();
+                *writer += 1;
+            });
+
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            assert!(!writer.is_finished());
+            std::mem::drop(reader);
+
+            writer.join()
+        });
+        writer.unwrap();
+
+        assert_eq!(*lock.read_blocking(), 1);
+    }
+}
+
+/// Model checks every interleaving of the lock. Run with:
+///
+/// `RUSTFLAGS="--cfg loom" cargo test --release --lib prw_lock::loom_tests`
+///
+/// The data is held in a loom cell, which panics if it is accessed mutably while anything else
+/// accesses it, or if an access isn't ordered after the previous write.
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::PrwLock;
+    use loom::{cell::UnsafeCell, sync::Arc, thread};
+
+    #[test]
+    fn writers_are_exclusive() {
+        loom::model(|| {
+            let lock = Arc::new(PrwLock::new(UnsafeCell::new(0)));
+
+            let threads = (0..2)
+                .map(|_| {
+                    let lock = lock.clone();
+                    thread::spawn(move || {
+                        if let Ok(writer) = lock.try_write() {
+                            writer.with_mut(|data| unsafe { *data += 1 });
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            if let Ok(reader) = lock.try_read() {
+                reader.with(|data| unsafe { assert!(*data <= 2) });
+            }
+
+            for thread in threads {
+                thread.join().unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn readers_share_access() {
+        loom::model(|| {
+            let lock = Arc::new(PrwLock::new(UnsafeCell::new(1)));
+
+            let reader = {
+                let lock = lock.clone();
+                thread::spawn(move || {
+                    if let Ok(reader) = lock.try_read() {
+                        reader.with(|data| unsafe { assert_eq!(*data, 1) });
+                    }
+                })
+            };
+
+            let handle = lock.read();
+            handle.with(|data| unsafe { assert_eq!(*data, 1) });
+
+            // Can't get a writer while the handle is alive
+            assert!(lock.try_write().is_err());
+            drop(handle);
+
+            reader.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn handles_dropped_on_other_threads() {
+        loom::model(|| {
+            let lock = Arc::new(PrwLock::new(UnsafeCell::new(0)));
+
+            // Write on another thread and drop the handle there
+            let writer = lock.write();
+            let thread = thread::spawn(move || {
+                writer.with_mut(|data| unsafe { *data = 1 });
+            });
+
+            // The write must be visible once the handle is released
+            let reader = lock.read_blocking();
+            reader.with(|data| unsafe { assert_eq!(*data, 1) });
+            drop(reader);
+
+            thread.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn blocking_writer_waits_for_reader() {
+        loom::model(|| {
+            let lock = Arc::new(PrwLock::new(UnsafeCell::new(0)));
+
+            let reader = lock.read();
+            let thread = {
+                let lock = lock.clone();
+                thread::spawn(move || {
+                    let writer = lock.write_blocking();
+                    writer.with_mut(|data| unsafe { *data += 1 });
+                })
+            };
+
+            reader.with(|data| unsafe { assert_eq!(*data, 0) });
+            drop(reader);
+
+            thread.join().unwrap();
+            lock.read().with(|data| unsafe { assert_eq!(*data, 1) });
+        });
+    }
 }
@@ -0,0 +1,310 @@
+//! Queries over components that are only known at runtime, for scripting and modding layers.
+
+use crate::{
+    archetype::{archetypes::Archetypes, blob::BlobVec, Archetype},
+    component::{registry::ComponentId, StorageKind},
+    entity::Entity,
+    prw_lock::{PrwReadHandle, PrwWriteHandle},
+};
+
+/// Describes the components a dynamic query accesses and how. Matches every entity that has all
+/// of the components.
+#[derive(Debug, Default, Clone)]
+pub struct DynamicQuery {
+    /// Components in the order their pointers are yielded, and whether they are written.
+    components: Vec<(ComponentId, bool)>,
+}
+
+/// Iterates over the rows matched by a `DynamicQuery`. Holds access to the component buffers
+/// until dropped, so the pointers it yields are only valid while it exists.
+pub struct DynamicQueryIter {
+    /// Sets left to iterate over, the current one last.
+    sets: Vec<DynamicSet>,
+    /// Pointers to the components of the current row.
+    row: Vec<*mut u8>,
+    /// Index of the next row within the current set.
+    idx: usize,
+    len: usize,
+}
+
+/// The entities and components of a single archetype matched by a dynamic query.
+struct DynamicSet {
+    entities: PrwReadHandle<Vec<Entity>>,
+    /// Keep the buffers the pointers point into locked.
+    #[allow(dead_code)]
+    handles: Vec<DynamicHandle>,
+    /// Pointer to the first component and component size of each column, in query order.
+    columns: Vec<(*mut u8, usize)>,
+}
+
+enum DynamicHandle {
+    Read(#[allow(dead_code)] PrwReadHandle<BlobVec>),
+    Write(#[allow(dead_code)] PrwWriteHandle<BlobVec>),
+}
+
+impl DynamicQuery {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a component the query reads.
+    ///
+    /// Panics if the component is already part of the query or is stored in a sparse set.
+    pub fn read(self, id: ComponentId) -> Self {
+        self.with(id, false)
+    }
+
+    /// Adds a component the query writes.
+    ///
+    /// Panics if the component is already part of the query or is stored in a sparse set.
+    pub fn write(self, id: ComponentId) -> Self {
+        self.with(id, true)
+    }
+
+    /// Components the query accesses, in the order their pointers are yielded, and whether they
+    /// are written.
+    #[inline]
+    pub fn components(&self) -> &[(ComponentId, bool)] {
+        &self.components
+    }
+
+    /// Creates an archetype which has every component the query accesses.
+    pub fn archetype(&self) -> Archetype {
+        self.archetype_where(|_, _| true)
+    }
+
+    /// Creates an archetype which contains only components that are read. Tags are left out,
+    /// since their data is never accessed.
+    pub fn read_archetype(&self) -> Archetype {
+        self.archetype_where(|id, write| !write && !id.info().is_tag())
+    }
+
+    /// Creates an archetype which contains only components that are written. Tags are left out.
+    pub fn write_archetype(&self) -> Archetype {
+        self.archetype_where(|id, write| write && !id.info().is_tag())
+    }
+
+    fn with(mut self, id: ComponentId, write: bool) -> Self {
+        let info = id.info();
+        assert!(
+            self.components.iter().all(|(other, _)| *other != id),
+            "Component {} accessed twice in dynamic query",
+            info.name
+        );
+        assert!(
+            info.storage == StorageKind::Table,
+            "Component {} is stored in a sparse set, which dynamic queries can't access",
+            info.name
+        );
+        self.components.push((id, write));
+        self
+    }
+
+    fn archetype_where(&self, filter: impl Fn(ComponentId, bool) -> bool) -> Archetype {
+        let mut archetype = Archetype::default();
+        for (id, write) in &self.components {
+            if filter(*id, *write) {
+                archetype.add_component_by_id(*id);
+            }
+        }
+        archetype
+    }
+}
+
+impl DynamicQueryIter {
+    pub(crate) fn new(archetypes: &Archetypes, query: &DynamicQuery) -> Self {
+        let archetype = query.archetype();
+        let mut len = 0;
+
+        let mut sets = Vec::default();
+        for descriptor in archetypes.descriptors() {
+            if !archetype.subset_of(&descriptor.archetype) {
+                continue;
+            }
+
+            let entities = archetypes.get_entity_buffers().get(descriptor.entities);
+            if entities.is_empty() {
+                continue;
+            }
+            len += entities.len();
+
+            let mut handles = Vec::with_capacity(query.components.len());
+            let mut columns = Vec::with_capacity(query.components.len());
+            for (id, write) in &query.components {
+                let info = id.info();
+                if info.is_tag() {
+                    // Tags have no data buffer, and an aligned pointer is valid for them
+                    columns.push((info.layout.align() as *mut u8, 0));
+                    continue;
+                }
+
+                let buffers = archetypes
+                    .get_buffers(*id)
+                    .expect("Requested non existant storage");
+                let row = descriptor.map[id];
+                let size = info.layout.size();
+
+                if *write {
+                    let mut handle = buffers.get_mut(row);
+                    columns.push((handle.as_mut_ptr(), size));
+                    handles.push(DynamicHandle::Write(handle));
+                } else {
+                    let handle = buffers.get(row);
+                    // Never written through, since the component is only read
+                    columns.push((handle.as_ptr() as *mut u8, size));
+                    handles.push(DynamicHandle::Read(handle));
+                }
+            }
+
+            sets.push(DynamicSet {
+                entities,
+                handles,
+                columns,
+            });
+        }
+
+        Self {
+            sets,
+            row: Vec::with_capacity(query.components.len()),
+            idx: 0,
+            len,
+        }
+    }
+
+    /// Moves to the next row, returning its entity and a pointer to each component in the order
+    /// they were added to the query. Components that are only read must not be written through
+    /// their pointer.
+    pub fn next_row(&mut self) -> Option<(Entity, &[*mut u8])> {
+        loop {
+            let set = self.sets.last()?;
+
+            if self.idx < set.entities.len() {
+                let idx = self.idx;
+                self.row.clear();
+                self.row.extend(
+                    set.columns
+                        .iter()
+                        .map(|(ptr, size)| unsafe { ptr.add(idx * size) }),
+                );
+                self.idx += 1;
+                return Some((set.entities[idx], &self.row));
+            }
+
+            self.sets.pop();
+            self.idx = 0;
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of rows the query visits in total.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::Layout;
+
+    use crate::{
+        archetype::Archetype,
+        component::{
+            registry::{ComponentId, ComponentRegistry},
+            Component, StorageKind,
+        },
+        dispatcher::Dispatcher,
+        system::{query::QueryGenerator, System},
+        world::World,
+    };
+
+    use super::DynamicQuery;
+
+    #[repr(transparent)]
+    struct Position(u32);
+
+    impl Component for Position {}
+
+    /// Adds the value of a component only known at runtime to `Position`.
+    struct ApplyBonus {
+        bonus: ComponentId,
+    }
+
+    impl System for ApplyBonus {
+        type Components = ();
+
+        fn tick(&mut self, gen: QueryGenerator) {
+            let query = DynamicQuery::new()
+                .read(self.bonus)
+                .write(ComponentId::of::<Position>());
+
+            let mut rows = gen.create_dynamic(&query);
+            while let Some((_, components)) = rows.next_row() {
+                unsafe {
+                    let bonus = components[0].cast::<u32>().read_unaligned();
+                    (*components[1].cast::<Position>()).0 += bonus;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dynamic_components_in_systems() {
+        let bonus =
+            unsafe { ComponentRegistry::register_dynamic("Bonus", Layout::new::<u32>(), None) };
+        let position = ComponentId::of::<Position>();
+
+        let mut world = World::new();
+        let entities = (1..=3u32)
+            .map(|i| {
+                let (x, value) = (i.to_ne_bytes(), (i * 10).to_ne_bytes());
+                unsafe { world.create_dynamic(&[(position, &x[..]), (bonus, &value[..])]) }
+            })
+            .collect::<Vec<_>>();
+        world.create((vec![Position(100)],));
+
+        let mut read = Archetype::default();
+        read.add_component_by_id(bonus);
+        let mut write = Archetype::default();
+        write.add_component_by_id(position);
+
+        let mut builder = Dispatcher::builder().thread_count(1);
+        builder.with_system_accessing(ApplyBonus { bonus }, &read, &write, &[]);
+        let mut dispatcher = builder.build();
+        dispatcher.run(&mut world);
+
+        let mut rows = world.query_dynamic(&DynamicQuery::new().read(position));
+        assert_eq!(rows.len(), 4);
+        let mut positions = Vec::default();
+        while let Some((entity, components)) = rows.next_row() {
+            positions.push((entity, unsafe { (*components[0].cast::<Position>()).0 }));
+        }
+        positions.sort_by_key(|(_, position)| *position);
+
+        assert_eq!(
+            positions,
+            vec![
+                (entities[0], 11),
+                (entities[1], 22),
+                (entities[2], 33),
+                (positions[3].0, 100)
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "which dynamic queries can't access")]
+    fn sparse_components_in_dynamic_queries() {
+        struct Selected;
+        impl Component for Selected {
+            const STORAGE: StorageKind = StorageKind::SparseSet;
+        }
+
+        DynamicQuery::new().read(ComponentId::of::<Selected>());
+    }
+}
@@ -1,26 +1,225 @@
+pub mod dynamic;
 pub mod query;
 
-use crate::{archetype::archetypes::Archetypes, component::filter::ComponentFilter};
+use std::{any::TypeId, cell::RefCell, collections::HashMap};
 
-use self::query::QueryGenerator;
+use crate::{
+    archetype::Archetype,
+    component::{
+        filter::ComponentAccess,
+        registry::{ComponentId, ComponentRegistry},
+    },
+    event::{Event, EventReader, EventWriter, Events},
+    world::World,
+};
+
+use self::query::{QueryCache, QueryGenerator};
 
 /// A system is what performs the actual logic within an ECS. It operates on a subset of entities
 /// that match a particular archetype.
 pub trait System {
-    /// When creating a system, you use this type to define what subset of components your
-    /// system is going to operate on.
-    type Components: ComponentFilter;
+    /// When creating a system, you use this type to define what subset of components and events
+    /// your system is going to operate on.
+    type Components: SystemAccess;
 
     /// Runs a single iteration of the system.
     fn tick(&mut self, gen: QueryGenerator);
 }
 
 pub trait GenericSystem {
-    fn generic_tick(&mut self, archetypes: &Archetypes);
+    fn generic_tick(&mut self, world: &World, state: &SystemState);
+}
+
+/// Represents a request for access on a single piece of data a system uses (a component type or
+/// an event channel).
+pub trait SystemAccessItem {
+    /// Indicates this access type needs mutable access.
+    const MUTABLE: bool;
+
+    /// Indicates the data is actually accessed. Access to tags, or filters that only check
+    /// whether an entity has a component, never conflicts with other systems.
+    const ACCESSED: bool = true;
+
+    /// ID of the data being accessed. Two systems are incompatible if one of them writes to
+    /// data with the same ID as data the other one accesses.
+    fn id() -> ComponentId;
+}
+
+/// Describes everything a system needs access to while it runs.
+pub trait SystemAccess {
+    /// Creates an archetype which has every piece of data the system accesses.
+    fn archetype() -> Archetype;
+
+    /// Creates an archetype which contains only data that is read.
+    fn read_archetype() -> Archetype;
+
+    /// Creates an archetype which contains only data that is written.
+    fn write_archetype() -> Archetype;
+}
+
+/// Data the dispatcher keeps for each system in between runs.
+#[derive(Default)]
+pub struct SystemState {
+    /// Maps the ID of each event queue the system reads from to the number of events the
+    /// system has already seen.
+    pub(crate) event_cursors: RefCell<HashMap<ComponentId, usize>>,
+    /// Maps the type ID of each component filter the system has created a query with to the
+    /// archetypes that matched it.
+    pub(crate) queries: RefCell<HashMap<TypeId, QueryCache>>,
+    /// Data the system reads in addition to `System::Components`, declared at runtime when the
+    /// system was added to the dispatcher.
+    pub(crate) extra_read: Archetype,
+    /// Data the system writes in addition to `System::Components`.
+    pub(crate) extra_write: Archetype,
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    /// Name of the system running on the current thread.
+    static CURRENT_SYSTEM: std::cell::Cell<Option<&'static str>> =
+        const { std::cell::Cell::new(None) };
+}
+
+/// Marks the current thread as running a system until dropped. Only does anything in debug
+/// builds, where it is used to name the systems involved in access conflicts.
+pub(crate) struct SystemScope {
+    #[cfg(debug_assertions)]
+    previous: Option<&'static str>,
+}
+
+impl SystemScope {
+    #[inline]
+    pub fn enter(_name: &'static str) -> Self {
+        Self {
+            #[cfg(debug_assertions)]
+            previous: CURRENT_SYSTEM.with(|current| current.replace(Some(_name))),
+        }
+    }
+}
+
+impl Drop for SystemScope {
+    #[inline]
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        CURRENT_SYSTEM.with(|current| current.set(self.previous));
+    }
+}
+
+/// Name of the system running on the current thread. Always `None` in release builds.
+#[inline]
+pub(crate) fn current_system() -> Option<&'static str> {
+    #[cfg(debug_assertions)]
+    return CURRENT_SYSTEM.with(|current| current.get());
+
+    #[cfg(not(debug_assertions))]
+    None
 }
 
 impl<T: System> GenericSystem for T {
-    fn generic_tick(&mut self, archetypes: &Archetypes) {
-        self.tick(QueryGenerator::new::<T::Components>(archetypes));
+    fn generic_tick(&mut self, world: &World, state: &SystemState) {
+        let _scope = SystemScope::enter(std::any::type_name::<T>());
+        self.tick(QueryGenerator::new::<T::Components>(world, state));
     }
 }
+
+impl<A: ComponentAccess> SystemAccessItem for A {
+    const MUTABLE: bool = A::MUTABLE;
+    const ACCESSED: bool = A::ACCESSED;
+
+    #[inline]
+    fn id() -> ComponentId {
+        ComponentId::of::<A::Component>()
+    }
+}
+
+impl<E: Event + 'static> SystemAccessItem for EventReader<E> {
+    const MUTABLE: bool = false;
+
+    #[inline]
+    fn id() -> ComponentId {
+        ComponentRegistry::id::<Events<E>>()
+    }
+}
+
+impl<E: Event + 'static> SystemAccessItem for EventWriter<E> {
+    const MUTABLE: bool = true;
+
+    #[inline]
+    fn id() -> ComponentId {
+        ComponentRegistry::id::<Events<E>>()
+    }
+}
+
+/// Implementation for systems that don't access any data.
+impl SystemAccess for () {
+    #[inline]
+    fn archetype() -> Archetype {
+        Archetype::default()
+    }
+
+    #[inline]
+    fn read_archetype() -> Archetype {
+        Archetype::default()
+    }
+
+    #[inline]
+    fn write_archetype() -> Archetype {
+        Archetype::default()
+    }
+}
+
+macro_rules! system_access_impl {
+    ( $n:expr, $( $name:ident )+ ) => {
+        impl<$($name: SystemAccessItem,)*> SystemAccess for ($($name,)*) {
+            #[inline]
+            fn archetype() -> Archetype {
+                let mut archetype = Archetype::default();
+                $(
+                    if $name::ACCESSED {
+                        archetype.add_component_by_id($name::id());
+                    }
+                )*
+                archetype
+            }
+
+            #[inline]
+            fn read_archetype() -> Archetype {
+                let mut archetype = Archetype::default();
+                $(
+                    if $name::ACCESSED && !$name::MUTABLE {
+                        archetype.add_component_by_id($name::id());
+                    }
+                )*
+                archetype
+            }
+
+            #[inline]
+            fn write_archetype() -> Archetype {
+                let mut archetype = Archetype::default();
+                $(
+                    if $name::ACCESSED && $name::MUTABLE {
+                        archetype.add_component_by_id($name::id());
+                    }
+                )*
+                archetype
+            }
+        }
+    }
+}
+
+system_access_impl! { 1, A }
+system_access_impl! { 2, A B }
+system_access_impl! { 3, A B C }
+system_access_impl! { 4, A B C D }
+system_access_impl! { 5, A B C D E }
+system_access_impl! { 6, A B C D E F }
+system_access_impl! { 7, A B C D E F G }
+system_access_impl! { 8, A B C D E F G H }
+system_access_impl! { 9, A B C D E F G H I }
+system_access_impl! { 10, A B C D E F G H I J }
+system_access_impl! { 11, A B C D E F G H I J K }
+system_access_impl! { 12, A B C D E F G H I J K L }
+system_access_impl! { 13, A B C D E F G H I J K L M }
+system_access_impl! { 14, A B C D E F G H I J K L M N }
+system_access_impl! { 15, A B C D E F G H I J K L M N O }
+system_access_impl! { 16, A B C D E F G H I J K L M N O P }
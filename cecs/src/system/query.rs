@@ -1,20 +1,30 @@
-use std::ptr::NonNull;
+use std::{any::TypeId, ptr::NonNull};
 
 use crate::{
     archetype::{access::DataBufferSet, archetypes::Archetypes, Archetype},
-    component::filter::ComponentFilter,
+    component::{filter::ComponentFilter, registry::ComponentRegistry},
     entity::Entity,
+    event::{Event, EventReader, EventWriter, Events},
     prw_lock::PrwReadHandle,
+    world::World,
+};
+
+use super::{
+    dynamic::{DynamicQuery, DynamicQueryIter},
+    SystemAccess, SystemState,
 };
 
 pub struct QueryGenerator<'a> {
-    archetypes: &'a Archetypes,
+    world: &'a World,
+    state: &'a SystemState,
     all_components: Archetype,
     mut_components: Archetype,
 }
 
 /// A query is an iterator that holds the references to the components being accessed. Iteration
 /// over a query must be VERY fast.
+///
+/// Entities that are missing one of the sparse set components in the filter are skipped.
 pub struct Query<C: ComponentFilter> {
     /// List of storage sets and entity buffers to loop over.
     sets: Vec<(FastEntityIterator, C::StorageSet)>,
@@ -22,60 +32,136 @@ pub struct Query<C: ComponentFilter> {
     set: Option<(FastEntityIterator, C::StorageSet)>,
     /// Current working set index.
     idx: usize,
+    /// Upper bound on the number of entities the query visits.
     len: usize,
 }
 
+/// Archetypes that matched a query the last time a system created it. Kept per system and per
+/// filter type, and only updated with the descriptors added to the world since.
+#[derive(Default)]
+pub(crate) struct QueryCache {
+    /// ID of the archetypes container the cache describes.
+    archetypes: Option<u64>,
+    /// Generation of the archetypes when the cache was last updated.
+    generation: u64,
+    /// Number of descriptors that have been checked against the filter.
+    checked: usize,
+    /// Index of the entity buffer of each matching archetype.
+    entities: Vec<usize>,
+    /// Indices of the component buffers of each matching archetype, as produced by
+    /// `ComponentFilter::columns`, back to back.
+    columns: Vec<usize>,
+}
+
 /// Special fast iterator for entity storages.
 struct FastEntityIterator {
     #[allow(dead_code)]
     handle: Option<PrwReadHandle<Vec<Entity>>>,
     ptr: NonNull<Entity>,
+    len: usize,
 }
 
 impl<'a> QueryGenerator<'a> {
-    pub fn new<C: ComponentFilter>(archetypes: &'a Archetypes) -> Self {
+    pub fn new<C: SystemAccess>(world: &'a World, state: &'a SystemState) -> Self {
+        let mut all_components = C::archetype();
+        let mut mut_components = C::write_archetype();
+        all_components.extend(&state.extra_read);
+        all_components.extend(&state.extra_write);
+        mut_components.extend(&state.extra_write);
+
         Self {
-            archetypes,
-            all_components: C::archetype(),
-            mut_components: C::write_archetype(),
+            world,
+            state,
+            all_components,
+            mut_components,
         }
     }
 
     /// Constructs a new query. Must ensure that the query being constructed is one that is allowed
     /// by what the system requested.
-    pub fn create<C: ComponentFilter>(&self) -> Query<C> {
-        assert!(C::read_archetype().subset_of(&self.all_components));
-        assert!(C::write_archetype().subset_of(&self.mut_components));
-        Query::new(self.archetypes)
+    pub fn create<C: ComponentFilter + 'static>(&self) -> Query<C> {
+        let mut queries = self.state.queries.borrow_mut();
+        let cache = queries.entry(TypeId::of::<C>()).or_insert_with(|| {
+            // Only needs to be checked the first time, since the system's access never changes
+            assert!(C::read_archetype().subset_of(&self.all_components));
+            assert!(C::write_archetype().subset_of(&self.mut_components));
+            QueryCache::default()
+        });
+        Query::new(&self.world.archetypes, cache)
+    }
+
+    /// Constructs a query over components described at runtime. Must ensure that the query being
+    /// constructed is one that is allowed by what the system requested, either through
+    /// `System::Components` or when it was added to the dispatcher.
+    pub fn create_dynamic(&self, query: &DynamicQuery) -> DynamicQueryIter {
+        assert!(query.read_archetype().subset_of(&self.all_components));
+        assert!(query.write_archetype().subset_of(&self.mut_components));
+        DynamicQueryIter::new(&self.world.archetypes, query)
+    }
+
+    /// Constructs a reader over every event of type `E` sent since the last time this system read
+    /// events of that type. Must ensure the system requested read access to the events.
+    pub fn event_reader<E: Event + 'static>(&self) -> EventReader<E> {
+        let id = ComponentRegistry::id::<Events<E>>();
+        assert!(self.all_components.contains(id));
+
+        let handle = self
+            .world
+            .events
+            .get::<E>()
+            .expect("Requested non existant event queue")
+            .read();
+
+        // Everything currently in the queue is considered seen from now on
+        let mut cursors = self.state.event_cursors.borrow_mut();
+        let cursor = cursors.entry(id).or_insert(0);
+        let begin = *cursor;
+        *cursor = handle.end();
+
+        EventReader::new(handle, begin)
+    }
+
+    /// Constructs a writer for events of type `E`. Must ensure the system requested write access
+    /// to the events.
+    pub fn event_writer<E: Event + 'static>(&self) -> EventWriter<E> {
+        assert!(self
+            .mut_components
+            .contains(ComponentRegistry::id::<Events<E>>()));
+
+        EventWriter::new(
+            self.world
+                .events
+                .get::<E>()
+                .expect("Requested non existant event queue")
+                .write(),
+        )
     }
 }
 
 impl<C: ComponentFilter> Query<C> {
-    fn new(archetypes: &Archetypes) -> Self {
-        // Generate the archetype for the filter
-        let archetype = C::archetype();
+    fn new(archetypes: &Archetypes, cache: &mut QueryCache) -> Self {
+        cache.update::<C>(archetypes);
 
         let mut len = 0;
 
-        // Find all archetype descriptors that our archetype is a subset of and generate data
-        // buffer sets with them and their corresponding entites.
+        // Generate data buffer sets for every matching archetype and their corresponding entities
         let mut sets = Vec::default();
-        for descriptor in archetypes.descriptors() {
-            // Must be compatible
-            if archetype.subset_of(&descriptor.archetype) {
-                // Grab entity storage
-                let handle = archetypes.get_entity_buffers().get(descriptor.entities);
-
-                // Must have non-zero entity count
-                if handle.len() != 0 {
-                    len += handle.len();
-
-                    // Add set and entity buffer
-                    sets.push((
-                        FastEntityIterator::new(handle),
-                        C::make_storage_set(descriptor, archetypes),
-                    ));
-                }
+        let stride = cache.columns.len() / cache.entities.len().max(1);
+        for (i, entities) in cache.entities.iter().enumerate() {
+            // Grab entity storage
+            let handle = archetypes.get_entity_buffers().get(*entities);
+
+            // Must have non-zero entity count
+            if !handle.is_empty() {
+                len += handle.len();
+
+                // Add set and entity buffer
+                let set = C::make_storage_set(
+                    &cache.columns[i * stride..][..stride],
+                    archetypes,
+                    sets.last().map(|(_, set)| set),
+                );
+                sets.push((FastEntityIterator::new(handle), set));
             }
         }
 
@@ -91,12 +177,14 @@ impl<C: ComponentFilter> Query<C> {
     }
 
     #[inline]
-    fn is_empty(&self) -> bool {
+    pub fn is_empty(&self) -> bool {
         self.len == 0
     }
 
+    /// Number of entities the query visits. If the filter contains sparse set components, this is
+    /// only an upper bound, since entities missing those components are skipped.
     #[inline]
-    fn len(&self) -> usize {
+    pub fn len(&self) -> usize {
         self.len
     }
 }
@@ -107,24 +195,54 @@ impl<C: ComponentFilter> Iterator for Query<C> {
     #[inline(always)]
     fn next(&mut self) -> Option<Self::Item> {
         // Check if we have a working set
-        if let Some((entities, set)) = &mut self.set {
+        while let Some((entities, set)) = &mut self.set {
             // Grab the filter and entity
-            // NOTE: Safe to unwrap since sets are guaranteed not to be empty and if the set
-            // wasn't valid last loop, it would have been replaced with a valid one.
-            let filter = unsafe { set.fetch(self.idx) };
+            // NOTE: Safe since sets are guaranteed not to be empty and if the set wasn't valid
+            // last loop, it would have been replaced with a valid one.
             let entity = unsafe { entities.fetch(self.idx) };
+            let filter = unsafe { set.fetch(self.idx, entity) };
 
             // Move to the next set if the current is invalid
             self.idx += 1;
-            if !set.is_valid(self.idx) {
+            if self.idx == entities.len {
                 self.set = self.sets.pop();
                 self.idx = 0;
             }
 
-            Some((entity, filter))
-        } else {
-            None
+            if let Some(filter) = filter {
+                return Some((entity, filter));
+            }
+        }
+
+        None
+    }
+}
+
+impl QueryCache {
+    /// Checks every descriptor added since the last update against the filter.
+    fn update<C: ComponentFilter>(&mut self, archetypes: &Archetypes) {
+        if self.archetypes != Some(archetypes.id()) {
+            // Created for a different world, so nothing in the cache applies
+            *self = QueryCache {
+                archetypes: Some(archetypes.id()),
+                ..QueryCache::default()
+            };
+        } else if self.generation == archetypes.generation() {
+            return;
+        }
+
+        let archetype = C::table_archetype();
+        let excluded = C::excluded_archetype();
+        for descriptor in &archetypes.descriptors()[self.checked..] {
+            if archetype.subset_of(&descriptor.archetype) && !excluded.any_of(&descriptor.archetype)
+            {
+                self.entities.push(descriptor.entities);
+                C::columns(descriptor, &mut self.columns);
+            }
         }
+
+        self.generation = archetypes.generation();
+        self.checked = archetypes.descriptors().len();
     }
 }
 
@@ -133,7 +251,8 @@ impl Default for FastEntityIterator {
     fn default() -> Self {
         Self {
             handle: None,
-            ptr: NonNull::new(1 as *mut Entity).unwrap(),
+            ptr: NonNull::dangling(),
+            len: 0,
         }
     }
 }
@@ -141,11 +260,12 @@ impl Default for FastEntityIterator {
 impl FastEntityIterator {
     #[inline]
     fn new(handle: PrwReadHandle<Vec<Entity>>) -> Self {
-        debug_assert!(handle.len() != 0);
+        debug_assert!(!handle.is_empty());
 
         // Cast const to mut, but we never modify the buffer so it's totally cool
         let ptr = handle.as_ptr() as *mut Entity;
         FastEntityIterator {
+            len: handle.len(),
             handle: Some(handle),
             // Safe to unwrap since len != 0, which means the buffer must be allocated
             ptr: NonNull::new(ptr).expect("Empty lock given to fast entity iterator"),
@@ -157,3 +277,157 @@ impl FastEntityIterator {
         *self.ptr.as_ptr().add(idx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use crate::{
+        component::{
+            filter::{Read, With, Without, Write},
+            Component, StorageKind,
+        },
+        dispatcher::Dispatcher,
+        system::{System, SystemAccess},
+        world::World,
+    };
+
+    use super::QueryGenerator;
+    use crate::component::registry::ComponentId;
+
+    struct Position(u32);
+    struct Velocity;
+
+    impl Component for Position {}
+    impl Component for Velocity {}
+
+    struct SumPositions(Arc<AtomicUsize>);
+
+    impl System for SumPositions {
+        type Components = (Read<Position>,);
+
+        fn tick(&mut self, gen: QueryGenerator) {
+            let sum = gen
+                .create::<(Read<Position>,)>()
+                .map(|(_, (position,))| position.0 as usize)
+                .sum();
+            self.0.store(sum, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn cached_queries_see_new_archetypes() {
+        let sum = Arc::new(AtomicUsize::new(0));
+        let mut builder = Dispatcher::builder().thread_count(1);
+        builder.with_system(SumPositions(sum.clone()), &[]);
+        let mut dispatcher = builder.build();
+
+        let mut world = World::new();
+        world.create((vec![Position(1), Position(2)],));
+        dispatcher.run(&mut world);
+        assert_eq!(sum.load(Ordering::Relaxed), 3);
+
+        // Matching archetype added after the query was cached
+        world.create((vec![Position(4)], vec![Velocity]));
+        dispatcher.run(&mut world);
+        assert_eq!(sum.load(Ordering::Relaxed), 7);
+
+        // Different world with the same number of archetypes
+        let mut other = World::new();
+        other.create((vec![Velocity],));
+        other.create((vec![Position(8)],));
+        dispatcher.run(&mut other);
+        assert_eq!(sum.load(Ordering::Relaxed), 8);
+    }
+
+    #[test]
+    fn queries_skip_entities_without_sparse_components() {
+        struct Marked;
+
+        impl Component for Marked {
+            const STORAGE: StorageKind = StorageKind::SparseSet;
+        }
+
+        struct SumMarked(Arc<AtomicUsize>);
+
+        impl System for SumMarked {
+            type Components = (Read<Position>, Write<Marked>);
+
+            fn tick(&mut self, gen: QueryGenerator) {
+                let sum = gen
+                    .create::<(Read<Position>, Write<Marked>)>()
+                    .map(|(_, (position, _))| position.0 as usize)
+                    .sum();
+                self.0.store(sum, Ordering::Relaxed);
+            }
+        }
+
+        let sum = Arc::new(AtomicUsize::new(0));
+        let mut builder = Dispatcher::builder().thread_count(1);
+        builder.with_system(SumMarked(sum.clone()), &[]);
+        let mut dispatcher = builder.build();
+
+        // The sparse set doesn't exist yet
+        let mut world = World::new();
+        let entities = world.create((vec![Position(1), Position(2)], vec![Velocity, Velocity]));
+        world.create((vec![Position(4)],));
+        dispatcher.run(&mut world);
+        assert_eq!(sum.load(Ordering::Relaxed), 0);
+
+        world.add_component(entities[1], Marked);
+        world.create((vec![Position(8)], vec![Marked]));
+        dispatcher.run(&mut world);
+        assert_eq!(sum.load(Ordering::Relaxed), 10);
+    }
+
+    #[test]
+    fn tags_filter_without_storage() {
+        struct Player;
+        struct Frozen;
+
+        impl Component for Player {}
+        impl Component for Frozen {}
+
+        struct MovePlayers;
+
+        impl System for MovePlayers {
+            type Components = (Write<Position>, Read<Player>);
+
+            fn tick(&mut self, gen: QueryGenerator) {
+                for (_, (position, _, _)) in
+                    gen.create::<(Write<Position>, With<Player>, Without<Frozen>)>()
+                {
+                    position.0 += 10;
+                }
+            }
+        }
+
+        // Tags don't take part in access conflicts
+        type Access = (Write<Position>, Write<Player>);
+        assert_eq!(Access::archetype().len(), 1);
+        assert!(Access::write_archetype().contains(ComponentId::of::<Position>()));
+
+        let sum = Arc::new(AtomicUsize::new(0));
+        let mut builder = Dispatcher::builder().thread_count(1);
+        let moving = builder.with_system(MovePlayers, &[]);
+        builder.with_system(SumPositions(sum.clone()), &[moving]);
+        let mut dispatcher = builder.build();
+
+        let mut world = World::new();
+        world.create((vec![Position(1)], vec![Player]));
+        let frozen = world.create((vec![Position(2)], vec![Player], vec![Frozen]))[0];
+        world.create((vec![Position(4)],));
+        assert!(world.archetypes.get_component_buffers::<Player>().is_none());
+
+        dispatcher.run(&mut world);
+        assert_eq!(sum.load(Ordering::Relaxed), 17);
+
+        assert!(world.remove_component::<Frozen>(frozen).is_some());
+        assert!(world.remove_component::<Frozen>(frozen).is_none());
+        dispatcher.run(&mut world);
+        assert_eq!(sum.load(Ordering::Relaxed), 37);
+    }
+}
@@ -1,9 +1,17 @@
-use std::num::NonZeroU32;
+use std::{fmt, num::NonZeroU32};
 
 use crate::{
-    archetype::archetypes::{ArchetypeDescriptorId, Archetypes},
-    component::pack::ComponentPack,
+    archetype::{
+        archetypes::{ArchetypeDescriptorId, Archetypes, MemoryUsage},
+        report::StorageReport,
+        Archetype,
+    },
+    component::{
+        bundle::Bundle, is_tag, pack::ComponentPack, registry::ComponentId, Component, StorageKind,
+    },
     entity::Entity,
+    event::{Event, EventChannels},
+    system::dynamic::{DynamicQuery, DynamicQueryIter},
 };
 
 /// The world is where entities and components are stored, and the interface used for creating or
@@ -11,10 +19,20 @@ use crate::{
 #[derive(Default)]
 pub struct World {
     pub(crate) archetypes: Archetypes,
+    pub(crate) events: EventChannels,
     entities: Vec<EntityInfo>,
     free: Vec<u32>,
-    /// Cache for newly created entity handles.
-    entity_cache: Vec<Entity>,
+}
+
+impl fmt::Debug for World {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let report = self.report();
+        f.debug_struct("World")
+            .field("entities", &report.entities())
+            .field("archetypes", &report.archetypes)
+            .field("components", &report.components)
+            .finish_non_exhaustive()
+    }
 }
 
 struct EntityInfo {
@@ -32,43 +50,669 @@ impl World {
         Self::default()
     }
 
-    pub fn create(&mut self, mut components: impl ComponentPack) -> &[Entity] {
+    /// Creates an entity for each set of components in a pack of component vectors, returning
+    /// the created entities.
+    pub fn create(&mut self, mut components: impl ComponentPack) -> Vec<Entity> {
         assert!(components.is_valid());
 
-        // Create entity handles
-        self.entity_cache.reserve(components.len());
-        self.entity_cache.clear();
-
-        for _ in 0..components.len() {
-            if let Some(free) = self.free.pop() {
-                self.entity_cache.push(Entity::from_raw_parts(
-                    free,
-                    self.entities[free as usize].ver,
-                ));
-            } else {
-                let ver = NonZeroU32::new(1).unwrap();
-
-                self.entities.push(EntityInfo {
-                    ver,
-                    archetype: ArchetypeDescriptorId::default(),
-                    index: 0,
-                });
-
-                self.entity_cache
-                    .push(Entity::from_raw_parts(self.entities.len() as u32 - 1, ver));
-            }
-        }
+        let entities = (0..components.len())
+            .map(|_| self.allocate())
+            .collect::<Vec<_>>();
 
         // Move the components into their archetype
-        let (archetype, begin) = components.move_into(&self.entity_cache, &mut self.archetypes);
+        let (archetype, begin) = components.move_into(&entities, &mut self.archetypes);
 
         // Update the created entities archetypes
-        for (i, entity) in self.entity_cache.iter().enumerate() {
-            let mut info = &mut self.entities[entity.id() as usize];
+        for (i, entity) in entities.iter().enumerate() {
+            let info = &mut self.entities[entity.id() as usize];
             info.archetype = archetype;
             info.index = begin + i;
         }
 
-        &self.entity_cache
+        entities
+    }
+
+    /// Creates an entity from a bundle of components, such as a tuple of components.
+    ///
+    /// Panics if the bundle contains a component type more than once.
+    pub fn spawn<B: Bundle>(&mut self, bundle: B) -> Entity {
+        let entity = self.allocate();
+        let (archetype, index) = bundle.move_into(entity, &mut self.archetypes);
+
+        let info = &mut self.entities[entity.id() as usize];
+        info.archetype = archetype;
+        info.index = index;
+        entity
+    }
+
+    /// Creates an entity for every bundle of components, returning the created entities. The
+    /// components are collected first, so they are moved into storage all at once.
+    ///
+    /// Panics if the bundles contain a component type more than once.
+    pub fn spawn_batch<B: Bundle>(&mut self, bundles: impl IntoIterator<Item = B>) -> Vec<Entity> {
+        let bundles = bundles.into_iter();
+        let mut pack = B::pack(bundles.size_hint().0);
+        for bundle in bundles {
+            bundle.push_into(&mut pack);
+        }
+
+        if pack.is_empty() {
+            return Vec::default();
+        }
+        self.create(pack)
+    }
+
+    /// Creates an entity from components only known at runtime, given as the ID of each component
+    /// and the bytes of its value.
+    ///
+    /// Panics if a component is given twice, if the bytes don't match the size of the component, if
+    /// the component is stored in a sparse set, or if it is a tag with a Rust type. Values of tags
+    /// with a Rust type are kept by type, so they have to be added through `add_component`.
+    ///
+    /// # Safety
+    /// The bytes of each component must be a valid value of the component. The world takes
+    /// ownership of the values, so they must not be used or dropped afterwards.
+    pub unsafe fn create_dynamic(&mut self, components: &[(ComponentId, &[u8])]) -> Entity {
+        let mut archetype = Archetype::default();
+        for (id, bytes) in components {
+            let info = id.info();
+            assert_eq!(
+                bytes.len(),
+                info.layout.size(),
+                "Data doesn't match the size of component {}",
+                info.name
+            );
+            assert!(
+                !archetype.contains(*id),
+                "Component {} given twice",
+                info.name
+            );
+            assert!(
+                info.storage == StorageKind::Table,
+                "Component {} is stored in a sparse set, which can't be created dynamically",
+                info.name
+            );
+            assert!(
+                !info.is_tag() || info.type_id.is_none(),
+                "Component {} is a tag with a Rust type, which can't be created dynamically",
+                info.name
+            );
+
+            archetype.add_component_by_id(*id);
+            self.archetypes.create_buffers(*id);
+        }
+
+        let archetype = self.archetypes.get_or_create_archetype(archetype);
+        let descriptor = self.archetypes.descriptor(archetype);
+        let entities = descriptor.entities;
+        for (id, bytes) in components {
+            // Tags have no row
+            if let Some(row) = descriptor.map.get(id) {
+                self.archetypes
+                    .get_buffers(*id)
+                    .expect("Component storage missing")
+                    .get_mut(*row)
+                    .push_raw(bytes.as_ptr());
+            }
+        }
+
+        let entity = self.allocate();
+        let index = {
+            let mut entities = self.archetypes.get_entity_buffers().get_mut(entities);
+            entities.push(entity);
+            entities.len() - 1
+        };
+
+        let info = &mut self.entities[entity.id() as usize];
+        info.archetype = archetype;
+        info.index = index;
+        entity
+    }
+
+    /// Constructs a query over components only known at runtime, from outside of the dispatcher.
+    pub fn query_dynamic(&mut self, query: &DynamicQuery) -> DynamicQueryIter {
+        DynamicQueryIter::new(&self.archetypes, query)
+    }
+
+    /// Adds a component to an entity, moving the entity to the archetype that also has the
+    /// component unless the component has sparse set storage. If the entity already has a
+    /// component of the same type, it is replaced and the old component is returned instead.
+    ///
+    /// Panics if the entity has been destroyed.
+    pub fn add_component<T: Component + 'static>(
+        &mut self,
+        entity: Entity,
+        component: T,
+    ) -> Option<T> {
+        let (from, index) = self.location(entity);
+        let id = ComponentId::of::<T>();
+
+        if T::STORAGE == StorageKind::SparseSet {
+            return self
+                .archetypes
+                .create_sparse_set::<T>()
+                .write()
+                .insert(entity, component);
+        }
+
+        if is_tag::<T>() && self.archetypes.descriptor(from).archetype.contains(id) {
+            // Tag values are interchangeable, so any kept value stands in for the old one
+            let mut values = self
+                .archetypes
+                .get_tag_values::<T>()
+                .expect("Tag storage missing")
+                .write();
+            let old = values.last_mut().expect("Tag value missing");
+            return Some(std::mem::replace(old, component));
+        }
+
+        if let Some(row) = self.archetypes.descriptor(from).map.get(&id) {
+            let mut buffer = self
+                .archetypes
+                .get_component_buffers::<T>()
+                .expect("Component storage missing")
+                .get_mut(*row);
+            return Some(std::mem::replace(
+                &mut buffer.as_mut_slice::<T>()[index],
+                component,
+            ));
+        }
+
+        self.archetypes.create_component_buffers::<T>();
+        let to = self.archetypes.add_edge(from, id);
+        let (new_index, swapped) = self.archetypes.move_entity(index, from, to);
+
+        if is_tag::<T>() {
+            self.archetypes
+                .get_tag_values::<T>()
+                .expect("Tag storage missing")
+                .write()
+                .push(component);
+        } else {
+            let row = self.archetypes.descriptor(to).map[&id];
+            self.archetypes
+                .get_component_buffers::<T>()
+                .expect("Component storage missing")
+                .get_mut(row)
+                .push(component);
+        }
+
+        self.relocate(entity, to, new_index, index, swapped);
+        None
+    }
+
+    /// Removes a component from an entity, moving the entity to the archetype without the
+    /// component unless the component has sparse set storage. Returns `None` if the entity
+    /// doesn't have the component.
+    ///
+    /// Panics if the entity has been destroyed.
+    pub fn remove_component<T: Component + 'static>(&mut self, entity: Entity) -> Option<T> {
+        let (from, index) = self.location(entity);
+        let id = ComponentId::of::<T>();
+
+        if T::STORAGE == StorageKind::SparseSet {
+            return self
+                .archetypes
+                .get_sparse_set::<T>()?
+                .write()
+                .remove(entity);
+        }
+
+        let component = if is_tag::<T>() {
+            if !self.archetypes.descriptor(from).archetype.contains(id) {
+                return None;
+            }
+            self.archetypes
+                .get_tag_values::<T>()
+                .expect("Tag storage missing")
+                .write()
+                .pop()
+                .expect("Tag value missing")
+        } else {
+            let row = *self.archetypes.descriptor(from).map.get(&id)?;
+            self.archetypes
+                .get_component_buffers::<T>()
+                .expect("Component storage missing")
+                .get_mut(row)
+                .swap_remove::<T>(index)
+        };
+
+        let to = self.archetypes.remove_edge(from, id);
+        let (new_index, swapped) = self.archetypes.move_entity(index, from, to);
+
+        self.relocate(entity, to, new_index, index, swapped);
+        Some(component)
+    }
+
+    /// Registers an event type so systems can send and receive events of that type. Does nothing
+    /// if the event type is already registered.
+    pub fn add_events<E: Event + 'static>(&mut self) {
+        self.events.register::<E>();
+    }
+
+    /// Sends an event from outside of the dispatcher. Systems see the event on the next run.
+    ///
+    /// Panics if the event type was never registered.
+    pub fn send_event<E: Event + 'static>(&mut self, event: E) {
+        self.events
+            .get::<E>()
+            .expect("Sent event of unregistered type")
+            .write()
+            .send(event);
+    }
+
+    /// Frees any memory that isn't needed to hold the current entities and components.
+    /// Storage only ever grows otherwise.
+    pub fn shrink_to_fit(&mut self) {
+        self.archetypes.shrink_to_fit();
+        self.entities.shrink_to_fit();
+        self.free.shrink_to_fit();
+    }
+
+    /// Removes every archetype that has no entities, along with the data buffers of its
+    /// components. Archetypes are created again as soon as an entity needs them. Queries cached by
+    /// systems are rebuilt the next time they are created.
+    pub fn remove_empty_archetypes(&mut self) {
+        let remap = self.archetypes.remove_empty();
+        for info in &mut self.entities {
+            // Only unused entity slots can be left in a removed archetype
+            info.archetype = remap[usize::from(info.archetype)].unwrap_or_default();
+        }
+    }
+
+    /// Memory used by the entities and components of each archetype. Components with sparse set
+    /// storage aren't part of any archetype.
+    pub fn memory_by_archetype(&self) -> Vec<(&Archetype, MemoryUsage)> {
+        self.archetypes.memory_by_archetype()
+    }
+
+    /// Memory used by the components of each type, ordered by component ID. Tags use no memory
+    /// and aren't listed.
+    pub fn memory_by_component(&self) -> Vec<(ComponentId, MemoryUsage)> {
+        self.archetypes.memory_by_component()
+    }
+
+    /// Describes every archetype and component type in the world, along with how many entities
+    /// they hold and how much memory they use. Printing the report gives a readable summary.
+    pub fn report(&self) -> StorageReport {
+        self.archetypes.report()
+    }
+
+    /// Creates a new entity handle, reusing the ID of a destroyed entity if possible. The entity
+    /// must be placed into an archetype by the caller.
+    fn allocate(&mut self) -> Entity {
+        if let Some(free) = self.free.pop() {
+            return Entity::from_raw_parts(free, self.entities[free as usize].ver);
+        }
+
+        let ver = NonZeroU32::new(1).unwrap();
+        self.entities.push(EntityInfo {
+            ver,
+            archetype: ArchetypeDescriptorId::default(),
+            index: 0,
+        });
+        Entity::from_raw_parts(self.entities.len() as u32 - 1, ver)
+    }
+
+    /// Gets the archetype and index within the archetype of the components of an entity.
+    ///
+    /// Panics if the entity has been destroyed.
+    #[inline]
+    fn location(&self, entity: Entity) -> (ArchetypeDescriptorId, usize) {
+        let info = &self.entities[entity.id() as usize];
+        assert_eq!(info.ver.get(), entity.ver(), "Entity has been destroyed");
+        (info.archetype, info.index)
+    }
+
+    /// Updates the location of an entity that moved to another archetype, and of the entity that
+    /// took its old place.
+    #[inline]
+    fn relocate(
+        &mut self,
+        entity: Entity,
+        archetype: ArchetypeDescriptorId,
+        index: usize,
+        old_index: usize,
+        swapped: Option<Entity>,
+    ) {
+        let info = &mut self.entities[entity.id() as usize];
+        info.archetype = archetype;
+        info.index = index;
+
+        if let Some(swapped) = swapped {
+            self.entities[swapped.id() as usize].index = old_index;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        component::{registry::ComponentId, Component, StorageKind},
+        entity::Entity,
+    };
+
+    use super::World;
+
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    struct Position(u32);
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    struct Velocity(u32);
+
+    impl Component for Position {}
+    impl Component for Velocity {}
+
+    /// Reads a component of an entity directly from storage.
+    fn get<T: Component + Copy + 'static>(world: &World, entity: Entity) -> Option<T> {
+        let (archetype, index) = world.location(entity);
+        let descriptor = world.archetypes.descriptor(archetype);
+        let row = *descriptor.map.get(&ComponentId::of::<T>())?;
+        Some(
+            world
+                .archetypes
+                .get_component_buffers::<T>()
+                .unwrap()
+                .get(row)
+                .as_slice::<T>()[index],
+        )
+    }
+
+    #[test]
+    fn add_and_remove_components() {
+        let mut world = World::new();
+        let entities = world.create((vec![Position(0), Position(1), Position(2)],));
+        let (start, _) = world.location(entities[0]);
+
+        assert_eq!(world.add_component(entities[0], Velocity(10)), None);
+        assert_eq!(
+            world.add_component(entities[0], Velocity(11)),
+            Some(Velocity(10))
+        );
+
+        // The second move follows the cached edge instead of creating an archetype
+        let descriptors = world.archetypes.descriptors().len();
+        world.add_component(entities[1], Velocity(12));
+        assert_eq!(world.archetypes.descriptors().len(), descriptors);
+        let (moved, _) = world.location(entities[1]);
+        assert_eq!(
+            world.archetypes.descriptor(start).edges.add[&ComponentId::of::<Velocity>()],
+            moved
+        );
+
+        // The entity left behind was swapped into the first slot
+        for (i, entity) in entities.iter().enumerate() {
+            assert_eq!(get::<Position>(&world, *entity), Some(Position(i as u32)));
+        }
+        assert_eq!(get::<Velocity>(&world, entities[0]), Some(Velocity(11)));
+        assert_eq!(get::<Velocity>(&world, entities[1]), Some(Velocity(12)));
+        assert_eq!(get::<Velocity>(&world, entities[2]), None);
+
+        assert_eq!(
+            world.remove_component::<Velocity>(entities[0]),
+            Some(Velocity(11))
+        );
+        assert_eq!(world.remove_component::<Velocity>(entities[0]), None);
+        assert_eq!(world.location(entities[0]).0, start);
+        assert_eq!(get::<Velocity>(&world, entities[1]), Some(Velocity(12)));
+
+        // Removing the only component leaves the entity without any
+        assert_eq!(
+            world.remove_component::<Position>(entities[2]),
+            Some(Position(2))
+        );
+        let (empty, _) = world.location(entities[2]);
+        assert!(world.archetypes.descriptor(empty).archetype.is_empty());
+        for (i, entity) in entities.iter().take(2).enumerate() {
+            assert_eq!(get::<Position>(&world, *entity), Some(Position(i as u32)));
+        }
+    }
+
+    #[test]
+    fn sparse_components_stay_out_of_archetypes() {
+        #[derive(Debug, PartialEq)]
+        struct Selected;
+
+        impl Component for Selected {
+            const STORAGE: StorageKind = StorageKind::SparseSet;
+        }
+
+        let mut world = World::new();
+        let entities = world.create((vec![Position(0), Position(1)], vec![Selected, Selected]));
+        let (archetype, _) = world.location(entities[0]);
+        assert_eq!(world.archetypes.descriptor(archetype).archetype.len(), 1);
+
+        let descriptors = world.archetypes.descriptors().len();
+        assert_eq!(
+            world.remove_component::<Selected>(entities[0]),
+            Some(Selected)
+        );
+        assert_eq!(world.remove_component::<Selected>(entities[0]), None);
+        assert_eq!(world.add_component(entities[0], Selected), None);
+        assert_eq!(world.add_component(entities[1], Selected), Some(Selected));
+        assert_eq!(world.archetypes.descriptors().len(), descriptors);
+        assert_eq!(world.location(entities[0]).0, archetype);
+
+        let set = world
+            .archetypes
+            .get_sparse_set::<Selected>()
+            .unwrap()
+            .read();
+        assert!(entities.iter().all(|entity| set.contains(*entity)));
+    }
+
+    #[test]
+    fn remove_empty_archetypes_and_shrink() {
+        let mut world = World::new();
+        let entities = world.create((vec![Position(0), Position(1), Position(2)],));
+        let (start, _) = world.location(entities[0]);
+        for entity in &entities {
+            world.add_component(*entity, Velocity(entity.id()));
+        }
+        world.remove_component::<Velocity>(entities[2]);
+        assert_eq!(world.archetypes.descriptors().len(), 2);
+
+        // Only the archetype holding the third entity is left
+        world.remove_component::<Position>(entities[2]);
+        world.remove_empty_archetypes();
+        let descriptors = world.archetypes.descriptors();
+        assert_eq!(descriptors.len(), 2);
+        assert!(descriptors
+            .iter()
+            .all(|descriptor| descriptor.archetype.len() != 1));
+        for entity in &entities[..2] {
+            assert_eq!(
+                get::<Position>(&world, *entity),
+                Some(Position(entity.id()))
+            );
+            assert_eq!(
+                get::<Velocity>(&world, *entity),
+                Some(Velocity(entity.id()))
+            );
+        }
+
+        // Edges to the removed archetype were dropped, so it is created again
+        world.remove_component::<Velocity>(entities[1]);
+        assert_ne!(world.location(entities[1]).0, start);
+        assert_eq!(get::<Position>(&world, entities[1]), Some(Position(1)));
+        assert_eq!(get::<Velocity>(&world, entities[0]), Some(Velocity(0)));
+
+        let position = |world: &World| {
+            world
+                .memory_by_component()
+                .into_iter()
+                .find(|(id, _)| *id == ComponentId::of::<Position>())
+                .unwrap()
+                .1
+        };
+        assert_eq!(position(&world).used, 2 * std::mem::size_of::<Position>());
+        world.shrink_to_fit();
+        assert_eq!(position(&world).allocated, position(&world).used);
+
+        let total = world
+            .memory_by_archetype()
+            .iter()
+            .map(|(_, usage)| usage.used)
+            .sum::<usize>();
+        assert_eq!(total, 3 * std::mem::size_of::<Entity>() + 3 * 4);
+    }
+
+    #[test]
+    fn spawn_bundles() {
+        struct Player;
+        struct Selected;
+
+        impl Component for Player {}
+        impl Component for Selected {
+            const STORAGE: StorageKind = StorageKind::SparseSet;
+        }
+
+        let mut world = World::new();
+        let first = world.spawn((Position(0), Velocity(1)));
+        let second = world.spawn((Velocity(3), Position(2), Player, Selected));
+        let batch = world.spawn_batch((4..8).map(|i| (Position(i), Velocity(i * 10))));
+        assert!(world
+            .spawn_batch(std::iter::empty::<(Position,)>())
+            .is_empty());
+
+        // Component order doesn't matter, and tags and sparse components stay out of the columns
+        assert_eq!(world.location(batch[0]).0, world.location(first).0);
+        assert_eq!(get::<Position>(&world, first), Some(Position(0)));
+        assert_eq!(get::<Velocity>(&world, second), Some(Velocity(3)));
+        let (archetype, _) = world.location(second);
+        assert_eq!(world.archetypes.descriptor(archetype).archetype.len(), 3);
+        assert!(world.remove_component::<Selected>(second).is_some());
+        for (i, entity) in batch.iter().enumerate() {
+            let i = i as u32 + 4;
+            assert_eq!(get::<Position>(&world, *entity), Some(Position(i)));
+            assert_eq!(get::<Velocity>(&world, *entity), Some(Velocity(i * 10)));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Bundle contains a component type more than once")]
+    fn spawn_duplicate_components() {
+        World::new().spawn((Position(0), Velocity(1), Position(2)));
+    }
+
+    #[test]
+    fn derived_bundles() {
+        use crate::component::bundle::Bundle;
+
+        #[derive(Component, Debug, Copy, Clone, PartialEq)]
+        #[component(name = "Speed")]
+        struct Speed(u32);
+        #[derive(Component)]
+        struct Player;
+        #[derive(Component, Debug, PartialEq)]
+        #[component(storage = "sparse_set")]
+        struct Health(u32);
+
+        #[derive(Bundle)]
+        struct Mover {
+            position: Position,
+            speed: Speed,
+        }
+        #[derive(Bundle)]
+        struct PlayerBundle(Player, Health, Position);
+        #[derive(Bundle)]
+        struct Wrapper<T>(T);
+
+        assert_eq!(Health::STORAGE, StorageKind::SparseSet);
+        assert_eq!(ComponentId::of::<Speed>().info().name, "Speed");
+
+        let mut world = World::new();
+        let mover = world.spawn(Mover {
+            position: Position(0),
+            speed: Speed(1),
+        });
+        let player = world.spawn(PlayerBundle(Player, Health(10), Position(2)));
+        let batch = world.spawn_batch((3..6).map(|i| Mover {
+            position: Position(i),
+            speed: Speed(i * 10),
+        }));
+
+        let mut pack = PlayerBundle::pack(2);
+        PlayerBundle(Player, Health(20), Position(6)).push_into(&mut pack);
+        PlayerBundle(Player, Health(30), Position(7)).push_into(&mut pack);
+        let players = world.create(pack);
+        let wrapped = world.spawn(Wrapper(Position(8)));
+
+        // Sparse set components stay out of the archetype
+        assert_eq!(PlayerBundle::archetype().len(), 2);
+        assert_eq!(world.location(mover).0, world.location(batch[2]).0);
+        assert_eq!(world.location(player).0, world.location(players[1]).0);
+
+        assert_eq!(get::<Speed>(&world, mover), Some(Speed(1)));
+        assert_eq!(get::<Position>(&world, player), Some(Position(2)));
+        assert_eq!(get::<Position>(&world, batch[1]), Some(Position(4)));
+        assert_eq!(get::<Position>(&world, players[1]), Some(Position(7)));
+        assert_eq!(get::<Position>(&world, wrapped), Some(Position(8)));
+        assert_eq!(
+            world.remove_component::<Health>(players[0]),
+            Some(Health(20))
+        );
+        assert_eq!(world.remove_component::<Speed>(batch[2]), Some(Speed(50)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Bundle contains a component type more than once")]
+    fn derived_bundle_duplicates() {
+        #[derive(crate::component::bundle::Bundle)]
+        struct Twice(Position, Position);
+
+        World::new().spawn(Twice(Position(0), Position(1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "which can't be created dynamically")]
+    fn create_dynamic_sparse_components() {
+        struct Health(#[allow(dead_code)] u32);
+        impl Component for Health {
+            const STORAGE: StorageKind = StorageKind::SparseSet;
+        }
+
+        let health = 10u32.to_ne_bytes();
+        unsafe { World::new().create_dynamic(&[(ComponentId::of::<Health>(), &health[..])]) };
+    }
+
+    #[test]
+    fn tags_keep_their_values() {
+        #[derive(Debug, PartialEq)]
+        struct Player;
+        impl Component for Player {}
+
+        let kept = |world: &World| {
+            world
+                .archetypes
+                .get_tag_values::<Player>()
+                .unwrap()
+                .read()
+                .len()
+        };
+
+        let mut world = World::new();
+        let entities = world.create((vec![Position(0), Position(1)], vec![Player, Player]));
+        let spawned = world.spawn((Position(2), Player));
+        assert_eq!(kept(&world), 3);
+
+        // Adding a tag the entity already has hands back the old value
+        assert_eq!(world.add_component(spawned, Player), Some(Player));
+        assert_eq!(kept(&world), 3);
+
+        assert_eq!(world.remove_component::<Player>(entities[0]), Some(Player));
+        assert_eq!(world.remove_component::<Player>(entities[0]), None);
+        assert_eq!(kept(&world), 2);
+
+        assert_eq!(world.add_component(entities[0], Player), None);
+        assert_eq!(kept(&world), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "which can't be created dynamically")]
+    fn create_dynamic_typed_tags() {
+        struct Player;
+        impl Component for Player {}
+
+        unsafe { World::new().create_dynamic(&[(ComponentId::of::<Player>(), &[][..])]) };
     }
 }